@@ -1,19 +1,19 @@
-
-
-; SUBROUTINE: Divide two integers
-; ---------------------------------
-
-;     Input: $arg0: dividend as int, $arg1: divisor as int
-;    Output: $return's the arg0/arg1 as int division
-; Algorithm: TODO
-
-@start(divide, 2)
-    math_div_loop:
-                                        ; arg0 < arg1 → break
-    JLS     :math_div_done      $arg0   $arg1
-    ADD     $return             1
-    SUB     $arg0               $arg1
-    JMP     :math_div_loop              ; Loop iteration
-
-    math_div_done:
+; SUBROUTINE: Divide two integers
+; ---------------------------------
+
+;     Input: $dividend, $divisor as int
+;    Output: $quotient: dividend/divisor as int division,
+;            $remainder: dividend % divisor
+; Algorithm: Subtract the divisor until the dividend is smaller
+
+@start(divide, dividend, divisor -> quotient, remainder)
+    math_div_loop:
+                                        ; dividend < divisor → break
+    JLS     :math_div_done      $dividend   $divisor
+    ADD     $quotient           1
+    SUB     $dividend           $divisor
+    JMP     :math_div_loop              ; Loop iteration
+
+    math_div_done:
+    MOV     $remainder          $dividend
 @end()
\ No newline at end of file
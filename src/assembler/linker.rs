@@ -0,0 +1,230 @@
+//! The linker (`tiny link`)
+//!
+//! Combines object files into a program:
+//!
+//! 1. The code of the objects is placed one after another in the order they
+//!    were given. The program starts with the first object.
+//! 2. The shared slots of the subroutine calling convention (`$jump_back`,
+//!    `$return`, `$arg0`, ...) get one address for all objects. Then each
+//!    object gets a block for its auto-filled constants. Addresses that an
+//!    object uses explicitly are never handed out.
+//! 3. Labels and subroutine calls between the objects are resolved and the
+//!    relocations are applied.
+
+use std::borrow::ToOwned;
+use std::collections::HashMap;
+use assembler::object::{Object, is_common};
+use assembler::parser::ast::Ident;
+use assembler::parser::{Relocation, SourceLocation};
+use assembler::util::fatal;
+use machine::{WordSize, MEMORY_SIZE};
+
+
+/// Link the objects. Returns the binary (one `Vec` per instruction) and the
+/// addresses of all labels.
+pub fn link(objects: &[Object]) -> (Vec<Vec<WordSize>>, HashMap<Ident, u32>) {
+    // The start of each object's code
+    let mut code_bases = vec![];
+    let mut size = 0;
+    for object in objects.iter() {
+        code_bases.push(size);
+        size += object.code_size();
+
+        if size > MEMORY_SIZE {
+            fatal(format!("the program doesn't fit into memory ({} bytes, the limit is {})",
+                          size, MEMORY_SIZE), &object.location);
+        }
+    }
+
+    let labels = collect_labels(objects, &code_bases);
+    check_calls(objects);
+
+    // Lay out the data memory
+    let mut reserved = [false; MEMORY_SIZE];
+    for object in objects.iter() {
+        for address in object.reserved.iter() {
+            reserved[*address as usize] = true;
+        }
+    }
+
+    let mut commons: HashMap<Ident, usize> = HashMap::new();
+    for object in objects.iter() {
+        for symbol in object.symbols.iter().filter(|symbol| is_common(&symbol.name)) {
+            if !commons.contains_key(&symbol.name) {
+                let address = allocate(&mut reserved, symbol.size, &object.location);
+                commons.insert(symbol.name.clone(), address);
+            }
+        }
+    }
+
+    let data_bases: Vec<usize> = objects.iter()
+        .map(|object| allocate(&mut reserved, object.data_size, &object.location))
+        .collect();
+
+    // Apply the relocations
+    let mut binary = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        let mut code: Vec<WordSize> = object.code.iter()
+            .flat_map(|bytes| bytes.iter().cloned())
+            .collect();
+
+        for relocation in object.relocations.iter() {
+            let position = match *relocation {
+                Relocation::Code(position) | Relocation::Data(position, _)
+                    | Relocation::External(position, _, _) => position
+            };
+
+            if position >= code.len() {
+                fatal(format!("relocation outside of the code: {}", position), &object.location);
+            }
+
+            let address = match *relocation {
+                Relocation::Code(_) => code_bases[i] + code[position] as usize,
+                Relocation::Data(_, ref name) if is_common(name) => {
+                    // Keep the offset into the slot
+                    let offset = match object.symbols.iter().find(|s| s.name == *name) {
+                        Some(symbol) => code[position] as usize - symbol.offset,
+                        None => fatal(format!("unknown data symbol: {}", name), &object.location)
+                    };
+
+                    *commons.get(name).unwrap() + offset
+                },
+                Relocation::Data(..) => data_bases[i] + code[position] as usize,
+                Relocation::External(_, ref name, ref location) => {
+                    match labels.get(name) {
+                        Some(&(offset, _)) => offset as usize,
+                        None => fatal(format!("undefined label: {}", name), location)
+                    }
+                }
+            };
+
+            if address > 255 {
+                fatal(format!("relocated address out of range: {}", address), &object.location);
+            }
+
+            code[position] = address as WordSize;
+        }
+
+        // Split the code into instructions again
+        let mut start = 0;
+        for bytes in object.code.iter() {
+            binary.push(code[start .. start + bytes.len()].to_vec());
+            start += bytes.len();
+        }
+    }
+
+    let labels = labels.into_iter()
+        .map(|(name, (offset, _))| (name, offset))
+        .collect();
+
+    (binary, labels)
+}
+
+
+/// Collect the labels and subroutine entry points of all objects
+fn collect_labels(objects: &[Object],
+                  code_bases: &[usize]) -> HashMap<Ident, (u32, SourceLocation)> {
+    let mut labels: HashMap<Ident, (u32, SourceLocation)> = HashMap::new();
+
+    for (object, base) in objects.iter().zip(code_bases.iter()) {
+        let definitions = object.labels.iter()
+            .map(|label| (&label.name, label.offset, &label.location))
+            .chain(object.subroutines.iter()
+                .map(|routine| (&routine.name, routine.entry, &routine.location)));
+
+        for (name, offset, location) in definitions {
+            if let Some(&(_, ref previous)) = labels.get(name) {
+                fatal(format!("duplicate label: {} (already defined in {})", name, previous),
+                      location);
+            }
+
+            labels.insert(name.clone(), (*base as u32 + offset, location.clone()));
+        }
+    }
+
+    labels
+}
+
+/// Make sure the calls between objects match the subroutine definitions
+fn check_calls(objects: &[Object]) {
+    for call in objects.iter().flat_map(|object| object.calls.iter()) {
+        let routine = objects.iter()
+            .flat_map(|object| object.subroutines.iter())
+            .find(|routine| routine.name == call.name)
+            .unwrap_or_else(|| {
+                fatal(format!("undefined subroutine: {}", call.name), &call.location)
+            });
+
+        if call.argc != routine.argc {
+            fatal(format!("wrong argument count: found {} args, expected {}",
+                          call.argc, routine.argc), &call.location);
+        }
+
+        // Results may be omitted completely
+        if call.retc > 0 && call.retc != routine.retc {
+            fatal(format!("wrong result count: found {} results, expected {}",
+                          call.retc, routine.retc), &call.location);
+        }
+    }
+}
+
+/// Find and reserve a block of `size` free data addresses
+fn allocate(reserved: &mut [bool], size: usize, location: &SourceLocation) -> usize {
+    if size == 0 {
+        return 0
+    }
+    if size > reserved.len() {
+        fatal("out of data memory while linking".to_owned(), location)
+    }
+
+    let start = (0 .. reserved.len() - size + 1)
+        .find(|&start| reserved[start .. start + size].iter().all(|used| !used))
+        .unwrap_or_else(|| {
+            fatal("out of data memory while linking".to_owned(), location)
+        });
+
+    for used in reserved[start .. start + size].iter_mut() {
+        *used = true;
+    }
+
+    start
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::object::Object;
+
+    use super::link;
+
+    #[test]
+    fn test_link() {
+        // main: MOV $counter 1; JMP :print
+        let main = Object::parse("tiny-object 1\n\
+                                  data 1\n\
+                                  reserve 0\n\
+                                  symbol counter 0 1\n\
+                                  label main 0\n\
+                                  code 08 00 01\n\
+                                  code 0f 00\n\
+                                  reloc data 1 counter\n\
+                                  reloc extern 4 print main.asm:2\n", "main.o");
+
+        // print: DPRINT $return; JMP :print
+        let lib = Object::parse("tiny-object 1\n\
+                                 data 1\n\
+                                 symbol return 0 1\n\
+                                 subroutine print 0 0 1\n\
+                                 code 22 00\n\
+                                 code 0f 00\n\
+                                 reloc data 1 return\n\
+                                 reloc code 3\n", "lib.o");
+
+        let (binary, labels) = link(&[main, lib]);
+
+        // [0] is reserved, $return is shared and placed first
+        assert_eq!(binary, vec![vec![0x08, 0x02, 0x01], vec![0x0f, 0x05],
+                                vec![0x22, 0x01], vec![0x0f, 0x05]]);
+        assert_eq!(labels.len(), 2);
+    }
+}
@@ -0,0 +1,162 @@
+//! Assembly listings
+//!
+//! A listing shows every instruction of the binary with its address, its
+//! encoding and the source line it was assembled from:
+//!
+//! ```
+//! ; Lines marked with `+` were generated by a macro or a subroutine
+//!
+//! 0x00  0x08 0x00 0x0a         test.asm:2     MOV $a 10
+//!                                             loop:
+//! 0x03  0x0d 0x00 0x01       + test.asm:4     DEC $a
+//! 0x06  0x1f 0x03 0x00 0x00  + test.asm:5     JNZ :loop $a
+//! ```
+
+use std::collections::HashMap;
+use assembler::parser::ast::{Statement, StatementNode, Ident};
+use machine::WordSize;
+
+
+/// Build the listing for a program. `sources` contains the text of every file
+/// the program was assembled from.
+pub fn generate_listing(program: &[StatementNode], binary: &[Vec<WordSize>],
+                        labels: &HashMap<Ident, u32>,
+                        sources: &HashMap<String, String>) -> String {
+    let mut listing = String::new();
+    listing.push_str("; Lines marked with `+` were generated by a macro or a subroutine\n\n");
+
+    // Labels ordered by their address
+    let mut labels: Vec<(u32, String)> = labels.iter()
+        .map(|(name, offset)| (*offset, name.as_str().to_string()))
+        .collect();
+    labels.sort();
+    let mut labels = labels.into_iter().peekable();
+
+    // How many instructions were assembled from each source line
+    let mut line_count = HashMap::new();
+    for stmt in program.iter() {
+        let location = stmt.location.to_string();
+        let count = line_count.get(&location).map_or(0, |count| *count);
+        line_count.insert(location, count + 1);
+    }
+
+    let mut address = 0;
+    for (stmt, bytes) in program.iter().zip(binary.iter()) {
+        // Show the labels pointing to this instruction
+        while labels.peek().map_or(false, |&(offset, _)| offset <= address) {
+            let (_, name) = labels.next().unwrap();
+            listing.push_str(&format!("{:44}{}:\n", "", name));
+        }
+
+        let encoded = bytes.iter()
+            .map(|b| format!("{:#04x}", b))
+            .collect::<Vec<_>>()
+            .connect(" ");
+
+        let line = source_line(stmt, sources);
+        let generated = match line {
+            Some(line) => {
+                !is_original(stmt, line) || *line_count.get(&stmt.location.to_string()).unwrap() > 1
+            },
+            None => true  // Code of the subroutine machinery
+        };
+
+        let text = line.map(|l| l.to_string()).unwrap_or_else(|| stmt.to_string());
+        listing.push_str(&format!("{:#04x}  {:<20} {} {:<14} {}\n",
+                                  address, encoded, if generated { "+" } else { " " },
+                                  stmt.location.to_string(), text));
+
+        address += bytes.len() as u32;
+    }
+
+    // Labels pointing behind the last instruction
+    for (_, name) in labels {
+        listing.push_str(&format!("{:44}{}:\n", "", name));
+    }
+
+    listing
+}
+
+
+/// Get the source line a statement was parsed from
+fn source_line<'a>(stmt: &StatementNode,
+                   sources: &'a HashMap<String, String>) -> Option<&'a str> {
+    sources.get(&*stmt.location.filename)
+        .and_then(|text| text.lines().nth(stmt.location.lineno - 1))
+        .map(|line| line.trim())
+}
+
+/// Whether the source line contains the instruction itself (and not a macro
+/// or a pseudo instruction it was expanded from)
+fn is_original(stmt: &StatementNode, line: &str) -> bool {
+    // Skip a label defined on the same line
+    let line = match line.find(':') {
+        Some(pos) if pos > 0 && !line[.. pos].chars().any(|c| c.is_whitespace()) => {
+            line[pos + 1 ..].trim_left()
+        },
+        _ => line
+    };
+
+    match stmt.value {
+        Statement::Operation(ref mnem, _) => {
+            line.split(|c: char| c.is_whitespace()).next() == Some(&*mnem.to_string())
+        },
+        _ => false
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use assembler::codegen::generate_binary;
+    use assembler::parser::ast::Ident;
+    use assembler::parser::{Parser, Context, expand_syntax_extensions};
+
+    use super::generate_listing;
+
+    #[test]
+    fn test_listing() {
+        let text = "MOV [0] 1\nHALT".to_string();
+        let program = Parser::new(&text, "test.asm").parse();
+        let binary = vec![vec![0x08, 0x00, 0x01], vec![0xff]];
+
+        let mut labels = HashMap::new();
+        labels.insert(Ident("end".to_string()), 3);
+
+        let mut sources = HashMap::new();
+        sources.insert("test.asm".to_string(), text.clone());
+
+        let listing = generate_listing(&program, &binary, &labels, &sources);
+        let lines: Vec<&str> = listing.lines().skip(2).collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0x00  0x08 0x00 0x01"));
+        assert!(lines[0].ends_with("test.asm:1     MOV [0] 1"));
+        assert_eq!(lines[1].trim(), "end:");
+        assert!(lines[2].starts_with("0x03  0xff"));
+        assert!(!lines[2].contains("+"));
+    }
+
+    #[test]
+    fn test_generated_lines() {
+        let text = "loop: MOV [0] 1\nINC [0]\nHALT".to_string();
+        let mut program = Parser::new(&text, "test.asm").parse();
+        let mut ctx = Context::new();
+        expand_syntax_extensions(&mut program, &mut ctx);
+        let binary = generate_binary(program.clone());
+
+        let mut sources = HashMap::new();
+        sources.insert("test.asm".to_string(), text.clone());
+
+        let listing = generate_listing(&program, &binary, &ctx.labels, &sources);
+        let lines: Vec<&str> = listing.lines().skip(2).collect();
+
+        // Only the line of the pseudo instruction is marked
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].trim(), "loop:");
+        assert!(lines[1].ends_with("  test.asm:1     loop: MOV [0] 1"));
+        assert!(lines[2].ends_with("+ test.asm:2     INC [0]"));
+        assert!(lines[3].ends_with("  test.asm:3     HALT"));
+    }
+}
@@ -1,85 +1,328 @@
-#[macro_use] mod util;
-mod codegen;
-mod parser;
-
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-use super::Args;
-use machine::WordSize;
-
-
-pub fn main(args: Args) {
-    // Read source file
-    let input_path = Path::new(&args.arg_input);
-    let source = read_file(&input_path);
-
-    // Parse source file
-    let filename = input_path.iter().last().unwrap().to_string_lossy();
-    let mut source = parser::Parser::new(&source, &filename).parse();
-
-    if args.flag_v {
-        println!("Source:");
-        for stmt in source.iter() {
-            println!("{}", stmt);
-        }
-        print!("\n");
-    }
-
-    // Expand syntax extensions
-    parser::expand_syntax_extensions(&mut source);
-
-    if args.flag_v {
-        println!("Expanded source:");
-        for stmt in source.iter() {
-            println!("{}", stmt);
-        }
-        print!("\n");
-    }
-
-    // Generate binary
-    let binary = codegen::generate_binary(source);
-
-    if args.flag_bin {
-        write_binary(binary, &Path::new(&args.arg_output));
-    } else {
-        for stmt in binary.iter() {
-            for b in stmt.iter() {
-                print!("{:#04x} ", *b)
-            }
-            print!("\n");
-        }
-    }
-}
-
-
-fn read_file(input_path: &Path) -> String {
-    let mut file = match File::open(&input_path) {
-        Ok(f) => f,
-        Err(err) => panic!("Can't open {}: {}", input_path.display(), err)
-    };
-
-    let mut contents = String::new();
-    match file.read_to_string(&mut contents) {
-        Ok(contents) => contents,
-        Err(_) => panic!("Can't read {}", input_path.display())
-    };
-
-    contents
-}
-
-fn write_binary(binary: Vec<Vec<WordSize>>, output_path: &Path) {
-    let mut file = match File::create(output_path) {
-        Ok(f) => f,
-        Err(err) => panic!("Can't write to {}: {}", output_path.display(), err)
-    };
-
-    for stmt in binary.iter() {
-        for b in stmt.iter() {
-            match file.write_all(&[*b]) {
-                Ok(_) => {},
-                Err(err) => panic!("Can't write to {}: {}", output_path.display(), err)
-            }
-        }
-    }
-}
\ No newline at end of file
+#[macro_use] mod util;
+mod codegen;
+mod linker;
+mod listing;
+mod object;
+mod output;
+mod symbols;
+mod parser;
+
+use std::borrow::ToOwned;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use super::Args;
+use machine::WordSize;
+use self::parser::ast::Ident;
+
+
+pub fn main(args: Args) {
+    // Read source file
+    let input_path = Path::new(&args.arg_input);
+    let contents = read_file(&input_path);
+
+    // Parse source file
+    let filename = input_path.iter().last().unwrap().to_string_lossy();
+    let mut source = parser::Parser::new(&contents, &filename).parse();
+
+    if args.flag_v {
+        println!("Source:");
+        for stmt in source.iter() {
+            println!("{}", stmt);
+        }
+        print!("\n");
+    }
+
+    // Expand syntax extensions
+    let mut ctx = parser::Context::new();
+    ctx.object = args.flag_c;
+    ctx.optimize = args.flag_O;
+    for define in args.flag_D.iter() {
+        let (name, value) = parse_define(define);
+        ctx.defines.insert(name, value);
+    }
+
+    parser::expand_syntax_extensions(&mut source, &mut ctx);
+
+    if args.flag_E {
+        print!("{}", expanded_source(&source));
+        return
+    }
+
+    if args.flag_v {
+        println!("Expanded source:");
+        print!("{}\n", expanded_source(&source));
+
+        if ctx.inlined.len() > 0 {
+            println!("Inlined subroutines:");
+            for site in ctx.inlined.iter() {
+                println!("{} in {}: {} bytes (a call takes {} bytes)",
+                         site.name, site.location, site.inline_size, site.call_size);
+            }
+            print!("\n");
+        }
+
+        if ctx.removed.len() > 0 {
+            println!("Removed subroutines:");
+            for routine in ctx.removed.iter() {
+                let reason = if routine.inlined { "only inlined" } else { "never called" };
+                println!("{} in {}: {} bytes ({})",
+                         routine.name, routine.location, routine.size, reason);
+
+                for name in routine.constants.iter() {
+                    println!("    ${}", name);
+                }
+            }
+            print!("\n");
+        }
+
+        if ctx.optimized.len() > 0 {
+            println!("Optimizations:");
+            for opt in ctx.optimized.iter() {
+                println!("{}: {}", opt.location, opt.description);
+            }
+            print!("\n");
+        }
+    }
+
+    if args.flag_O {
+        let saved = ctx.optimized.iter().fold(0, |saved, opt| saved + opt.saved);
+        println!("Optimizer: {} bytes saved", saved);
+    }
+
+    if args.flag_memory_map {
+        print_memory_map(&ctx.memory_map);
+    }
+
+    if args.flag_size_report {
+        println!("Size report:");
+        println!("{}", ctx.size);
+    }
+
+    // Generate binary
+    let program = if args.flag_listing.is_empty() { vec![] } else { source.clone() };
+    let debug = debug_lines(&source);
+    let binary = codegen::generate_binary(source);
+
+    if !args.flag_listing.is_empty() {
+        // The listing shows the original lines of all files
+        let mut sources = HashMap::new();
+        sources.insert(filename.to_string(), contents.clone());
+        for import in ctx.imports.iter() {
+            sources.insert(import.clone(), read_file(&Path::new(import)));
+        }
+
+        let listing = listing::generate_listing(&program, &binary, &ctx.labels, &sources);
+        write_file(listing.as_bytes(), &Path::new(&args.flag_listing));
+    }
+
+    if !args.flag_cfg.is_empty() {
+        let dot = ctx.flow_graph.to_dot();
+        write_file(dot.as_bytes(), &Path::new(&args.flag_cfg));
+    }
+
+    if !args.flag_symbols.is_empty() {
+        let symbols = symbols::generate_symbols(&ctx);
+        write_file(symbols.as_bytes(), &Path::new(&args.flag_symbols));
+    }
+
+    if args.flag_c {
+        let object = object::Object::new(binary, &ctx);
+        write_file(object.to_string().as_bytes(), &Path::new(&args.arg_output));
+        return
+    }
+
+    write_output(&binary, &ctx.labels, Some(debug), &args, args.flag_bin);
+}
+
+
+pub fn link(args: Args) {
+    let objects: Vec<object::Object> = args.arg_object.iter()
+        .map(|path| object::Object::parse(&read_file(&Path::new(path)), path))
+        .collect();
+
+    let (binary, labels) = linker::link(&objects);
+
+    write_output(&binary, &labels, None, &args, true);
+}
+
+
+/// Assemble a program without any of the command line options
+#[cfg(test)]
+pub fn assemble(contents: &str, filename: &str) -> Vec<Vec<WordSize>> {
+    let mut source = parser::Parser::new(contents, filename).parse();
+    parser::expand_syntax_extensions(&mut source, &mut parser::Context::new());
+
+    codegen::generate_binary(source)
+}
+
+
+/// Write the binary in the format given on the command line, either to the
+/// output file or to stdout
+fn write_output(binary: &[Vec<WordSize>], labels: &HashMap<Ident, u32>, debug: Option<String>,
+                args: &Args, to_file: bool) {
+    let format = if !args.flag_format.is_empty() {
+        args.flag_format.parse().unwrap_or_else(|err: String| panic!("{}", err))
+    } else if to_file {
+        output::Format::Raw
+    } else {
+        output::Format::Dump
+    };
+
+    let output = output::generate_output(binary, labels, debug, format);
+
+    if to_file {
+        write_file(&output, &Path::new(&args.arg_output));
+    } else if format == output::Format::Raw || format == output::Format::Tiny {
+        panic!("Binary output has to be written to a file (use --bin)")
+    } else {
+        print!("{}", String::from_utf8(output).unwrap());
+    }
+}
+
+
+/// Format the expanded source (one statement per line) as printed by `-E`
+fn expanded_source(program: &[parser::ast::StatementNode]) -> String {
+    let mut lines = String::new();
+
+    for stmt in program.iter() {
+        lines.push_str(&format!("{}\n", stmt));
+    }
+
+    lines
+}
+
+/// Get the source location of every instruction for the debug section of a
+/// container (as `address file:line` lines)
+fn debug_lines(program: &[parser::ast::StatementNode]) -> String {
+    let mut lines = String::new();
+    let mut address = 0;
+
+    for stmt in program.iter() {
+        if stmt.value.size() > 0 {
+            lines.push_str(&format!("{} {}\n", address, stmt.location));
+            address += stmt.value.size();
+        }
+    }
+
+    lines
+}
+
+fn print_memory_map(memory_map: &[parser::Allocation]) {
+    println!("Memory map:");
+
+    for alloc in memory_map.iter() {
+        let symbol = match alloc.symbol {
+            Some(ref name) => format!("${}", name),
+            None if alloc.explicit => format!("[{}]", alloc.address),
+            None => "[_]".to_owned()
+        };
+
+        let symbol = if alloc.size > 1 {
+            format!("{} ({} bytes)", symbol, alloc.size)
+        } else {
+            symbol
+        };
+
+        println!("{:#04x}  {:<24} {}", alloc.address, symbol, alloc.location);
+    }
+
+    print!("\n");
+}
+
+
+/// Parse a constant definition from the command line (`name=value` or `name`).
+/// Names follow the rules of the source: lowercase letters, digits and `_`.
+fn parse_define(define: &str) -> (Ident, WordSize) {
+    let (name, value) = match define.find('=') {
+        Some(pos) => (&define[..pos], &define[pos + 1..]),
+        None => (define, "1")
+    };
+
+    let valid_name = name.chars().next().map_or(false, |c| c.is_alphabetic() && c.is_lowercase())
+        && name.chars().all(|c| (c.is_alphabetic() && c.is_lowercase()) || c.is_numeric() || c == '_');
+    if !valid_name {
+        panic!("Invalid constant name: {} (use lowercase letters, digits and `_`)", name)
+    }
+
+    match value.parse() {
+        Ok(value) => (Ident(name.to_owned()), value),
+        Err(_) => panic!("Invalid value for ${}: {}", name, value)
+    }
+}
+
+
+fn read_file(input_path: &Path) -> String {
+    let mut file = match File::open(&input_path) {
+        Ok(f) => f,
+        Err(err) => panic!("Can't open {}: {}", input_path.display(), err)
+    };
+
+    let mut contents = String::new();
+    match file.read_to_string(&mut contents) {
+        Ok(contents) => contents,
+        Err(_) => panic!("Can't read {}", input_path.display())
+    };
+
+    contents
+}
+
+fn write_file(contents: &[u8], output_path: &Path) {
+    let mut file = match File::create(output_path) {
+        Ok(f) => f,
+        Err(err) => panic!("Can't write to {}: {}", output_path.display(), err)
+    };
+
+    match file.write_all(contents) {
+        Ok(_) => {},
+        Err(err) => panic!("Can't write to {}: {}", output_path.display(), err)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::{Parser, Context, expand_syntax_extensions};
+    use super::{parse_define, expanded_source};
+
+    #[test]
+    fn test_expanded_source() {
+        // `-E` prints the program with all pseudo instructions lowered
+        let mut program = Parser::new("$a = [0]\n\
+                                       INC $a\n\
+                                       CLR $a\n\
+                                       JNZ :end $a\n\
+                                       end:\n\
+                                       HALT\n", "test.asm").parse();
+        expand_syntax_extensions(&mut program, &mut Context::new());
+
+        assert_eq!(expanded_source(&program), "ADD [0] 1\n\
+                                               MOV [0] 0\n\
+                                               JGT 10 [0] 0\n\
+                                               HALT\n");
+    }
+
+    #[test]
+    fn test_parse_define() {
+        let (name, value) = parse_define("level=2");
+        assert_eq!(name.as_str(), "level");
+        assert_eq!(value, 2);
+
+        let (name, value) = parse_define("debug_2");
+        assert_eq!(name.as_str(), "debug_2");
+        assert_eq!(value, 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_uppercase_define() {
+        parse_define("FOO");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_invalid_define_value() {
+        parse_define("level=high");
+    }
+}
@@ -0,0 +1,351 @@
+//! Object files
+//!
+//! `tiny asm -c` stores the assembled code together with everything the
+//! linker needs to combine it with other objects. Object files are text, one
+//! entry per line (`;` starts a comment):
+//!
+//! ```
+//! tiny-object 1
+//! data 3                          ; Bytes of auto-filled data memory
+//! reserve 16                      ; An explicitly used data address
+//! symbol counter 0 1              ; An auto-filled constant (offset and size)
+//! label main 0                    ; A label other objects can use
+//! subroutine print 14 1 1         ; Entry point, argument and result count
+//! call multiply 2 1 main.asm:12   ; A call of a subroutine from another object
+//! code 08 00 01                   ; The bytes of an instruction
+//! reloc code 7                    ; A byte holding a program address
+//! reloc data 4 counter            ; A byte holding a data address
+//! reloc extern 9 loop main.asm:5  ; A byte holding a label from another object
+//! ```
+//!
+//! All offsets are relative to the start of the object's code and data.
+
+use std::fmt;
+use std::rc::Rc;
+use assembler::parser::ast::Ident;
+use assembler::parser::{Context, Relocation, SourceLocation, dummy_source};
+use assembler::util::fatal;
+use machine::WordSize;
+
+
+/// The version of the object file format
+const VERSION: &'static str = "tiny-object 1";
+
+
+/// An auto-filled constant
+pub struct DataSymbol {
+    pub name: Ident,
+    pub offset: usize,
+    pub size: usize
+}
+
+/// A label other objects can jump to
+pub struct Label {
+    pub name: Ident,
+    pub offset: u32,
+    pub location: SourceLocation    // The line in the object file
+}
+
+/// A subroutine other objects can call
+pub struct Subroutine {
+    pub name: Ident,
+    pub entry: u32,
+    pub argc: usize,
+    pub retc: usize,
+    pub location: SourceLocation
+}
+
+/// A call of a subroutine from another object
+pub struct Call {
+    pub name: Ident,
+    pub argc: usize,
+    pub retc: usize,                // 0 if the results are omitted
+    pub location: SourceLocation    // The call in the source
+}
+
+pub struct Object {
+    pub location: SourceLocation,   // The start of the object file
+    pub code: Vec<Vec<WordSize>>,
+    pub data_size: usize,
+    pub reserved: Vec<WordSize>,
+    pub symbols: Vec<DataSymbol>,
+    pub labels: Vec<Label>,
+    pub subroutines: Vec<Subroutine>,
+    pub calls: Vec<Call>,
+    pub relocations: Vec<Relocation>
+}
+
+impl Object {
+    /// Build the object for an assembled program
+    pub fn new(code: Vec<Vec<WordSize>>, ctx: &Context) -> Object {
+        let mut labels: Vec<Label> = ctx.labels.iter()
+            .filter(|&(name, _)| !ctx.local_labels.contains(name))
+            .map(|(name, offset)| Label {
+                name: name.clone(),
+                offset: *offset,
+                location: dummy_source()
+            })
+            .collect();
+        labels.sort_by(|a, b| (a.offset, a.name.as_str()).cmp(&(b.offset, b.name.as_str())));
+
+        Object {
+            location: dummy_source(),
+            code: code,
+            data_size: ctx.memory_map.iter()
+                .filter(|alloc| !alloc.explicit)
+                .map(|alloc| alloc.address as usize + alloc.size)
+                .max()
+                .unwrap_or(0),
+            reserved: ctx.memory_map.iter()
+                .filter(|alloc| alloc.explicit)
+                .map(|alloc| alloc.address)
+                .collect(),
+            symbols: ctx.memory_map.iter()
+                .filter(|alloc| !alloc.explicit && alloc.symbol.is_some())
+                .map(|alloc| DataSymbol {
+                    name: alloc.symbol.clone().unwrap(),
+                    offset: alloc.address as usize,
+                    size: alloc.size
+                })
+                .collect(),
+            labels: labels,
+            subroutines: ctx.emitted.iter()
+                .map(|routine| Subroutine {
+                    name: routine.name.clone(),
+                    entry: *ctx.labels.get(&routine.name).unwrap(),
+                    argc: routine.params.len(),
+                    retc: routine.results.len(),
+                    location: dummy_source()
+                })
+                .collect(),
+            calls: ctx.external_calls.iter()
+                .map(|call| Call {
+                    name: call.name.clone(),
+                    argc: call.argc,
+                    retc: call.retc,
+                    location: call.location.clone()
+                })
+                .collect(),
+            relocations: ctx.relocations.clone()
+        }
+    }
+
+    /// Read an object file
+    pub fn parse(text: &str, filename: &str) -> Object {
+        let filename = Rc::new(filename.to_string());
+        let line_location = |lineno: usize| SourceLocation {
+            filename: filename.clone(),
+            lineno: lineno
+        };
+
+        let mut object = Object {
+            location: line_location(1),
+            code: vec![],
+            data_size: 0,
+            reserved: vec![],
+            symbols: vec![],
+            labels: vec![],
+            subroutines: vec![],
+            calls: vec![],
+            relocations: vec![]
+        };
+
+        let mut lines = text.lines();
+        if lines.next() != Some(VERSION) {
+            fatal(format!("not an object file (expected `{}`)", VERSION), &line_location(1));
+        }
+
+        for (i, line) in lines.enumerate() {
+            let location = line_location(i + 2);
+            let entry = line.split(';').next().unwrap();  // Strip comments
+            let words: Vec<&str> = entry.split(' ').filter(|w| !w.is_empty()).collect();
+            if words.len() == 0 {
+                continue
+            }
+
+            match (words[0], words.len()) {
+                ("data", 2) => object.data_size = number(words[1], &location),
+                ("reserve", 2) => object.reserved.push(number(words[1], &location)),
+                ("symbol", 4) => object.symbols.push(DataSymbol {
+                    name: Ident(words[1].to_string()),
+                    offset: number(words[2], &location),
+                    size: number(words[3], &location)
+                }),
+                ("label", 3) => object.labels.push(Label {
+                    name: Ident(words[1].to_string()),
+                    offset: number(words[2], &location),
+                    location: location
+                }),
+                ("subroutine", 5) => object.subroutines.push(Subroutine {
+                    name: Ident(words[1].to_string()),
+                    entry: number(words[2], &location),
+                    argc: number(words[3], &location),
+                    retc: number(words[4], &location),
+                    location: location
+                }),
+                ("call", 5) => object.calls.push(Call {
+                    name: Ident(words[1].to_string()),
+                    argc: number(words[2], &location),
+                    retc: number(words[3], &location),
+                    location: source_location(words[4], &location)
+                }),
+                ("code", _) => {
+                    let bytes = words[1..].iter()
+                        .map(|word| byte(word, &location))
+                        .collect();
+                    object.code.push(bytes);
+                },
+                ("reloc", 3) if words[1] == "code" => {
+                    object.relocations.push(Relocation::Code(number(words[2], &location)))
+                },
+                ("reloc", 4) if words[1] == "data" => {
+                    object.relocations.push(Relocation::Data(number(words[2], &location),
+                                                             Ident(words[3].to_string())))
+                },
+                ("reloc", 5) if words[1] == "extern" => {
+                    object.relocations.push(Relocation::External(
+                        number(words[2], &location),
+                        Ident(words[3].to_string()),
+                        source_location(words[4], &location)
+                    ))
+                },
+                _ => fatal(format!("invalid object file entry: {}", line), &location)
+            }
+        }
+
+        object
+    }
+
+    /// The number of bytes of code
+    pub fn code_size(&self) -> usize {
+        self.code.iter().fold(0, |size, bytes| size + bytes.len())
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{}", VERSION));
+        try!(writeln!(f, "data {}", self.data_size));
+
+        for address in self.reserved.iter() {
+            try!(writeln!(f, "reserve {}", address));
+        }
+        for symbol in self.symbols.iter() {
+            try!(writeln!(f, "symbol {} {} {}", symbol.name, symbol.offset, symbol.size));
+        }
+        for label in self.labels.iter() {
+            try!(writeln!(f, "label {} {}", label.name, label.offset));
+        }
+        for routine in self.subroutines.iter() {
+            try!(writeln!(f, "subroutine {} {} {} {}",
+                          routine.name, routine.entry, routine.argc, routine.retc));
+        }
+        for call in self.calls.iter() {
+            try!(writeln!(f, "call {} {} {} {}", call.name, call.argc, call.retc, call.location));
+        }
+
+        for bytes in self.code.iter() {
+            let bytes = bytes.iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .connect(" ");
+            try!(writeln!(f, "code {}", bytes));
+        }
+
+        for relocation in self.relocations.iter() {
+            try!(match *relocation {
+                Relocation::Code(position) => writeln!(f, "reloc code {}", position),
+                Relocation::Data(position, ref name) => {
+                    writeln!(f, "reloc data {} {}", position, name)
+                },
+                Relocation::External(position, ref name, ref location) => {
+                    writeln!(f, "reloc extern {} {} {}", position, name, location)
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Whether a data symbol is shared by all objects. These are the slots of the
+/// subroutine calling convention, so objects can call each other's subroutines.
+pub fn is_common(name: &Ident) -> bool {
+    let name = name.as_str();
+    let numbered = |prefix: &str| {
+        name.starts_with(prefix) && name[prefix.len()..].chars().all(|c| c.is_numeric())
+    };
+
+    name == "jump_back" || numbered("return") || (numbered("arg") && name != "arg")
+}
+
+fn number<T: ::std::str::FromStr>(word: &str, location: &SourceLocation) -> T {
+    word.parse().unwrap_or_else(|_| fatal(format!("invalid number: {}", word), location))
+}
+
+/// Parse a byte written as two hex digits
+fn byte(word: &str, location: &SourceLocation) -> WordSize {
+    let digits: Vec<u32> = word.chars().filter_map(|c| c.to_digit(16)).collect();
+
+    if word.len() != 2 || digits.len() != 2 {
+        fatal(format!("invalid byte: {}", word), location)
+    }
+
+    (digits[0] * 16 + digits[1]) as WordSize
+}
+
+/// Parse a source location written as `file:line`
+fn source_location(word: &str, location: &SourceLocation) -> SourceLocation {
+    let pos = word.rfind(':').unwrap_or_else(|| {
+        fatal(format!("invalid source location: {}", word), location)
+    });
+
+    SourceLocation {
+        filename: Rc::new(word[..pos].to_string()),
+        lineno: number(&word[pos + 1..], location)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::Relocation;
+    use assembler::parser::ast::Ident;
+
+    use super::{Object, is_common};
+
+    #[test]
+    fn test_round_trip() {
+        let text = "tiny-object 1\n\
+                    data 1\n\
+                    reserve 16\n\
+                    symbol counter 0 1\n\
+                    label main 0\n\
+                    subroutine print 5 1 1\n\
+                    call multiply 2 1 main.asm:12\n\
+                    code 08 00 01\n\
+                    code 0f 00\n\
+                    reloc code 4\n\
+                    reloc data 1 counter\n\
+                    reloc extern 4 loop main.asm:5\n";
+
+        let object = Object::parse(text, "main.o");
+        assert_eq!(object.code, vec![vec![0x08, 0x00, 0x01], vec![0x0f, 0x00]]);
+        assert_eq!(object.code_size(), 5);
+        assert_eq!(object.relocations[1], Relocation::Data(1, Ident("counter".to_string())));
+        assert_eq!(object.labels[0].location.lineno, 5);
+
+        assert_eq!(object.to_string(), text);
+    }
+
+    #[test]
+    fn test_common() {
+        assert!(is_common(&Ident("jump_back".to_string())));
+        assert!(is_common(&Ident("return".to_string())));
+        assert!(is_common(&Ident("return2".to_string())));
+        assert!(is_common(&Ident("arg0".to_string())));
+        assert!(!is_common(&Ident("arg".to_string())));
+        assert!(!is_common(&Ident("counter".to_string())));
+    }
+}
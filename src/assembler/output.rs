@@ -0,0 +1,388 @@
+//! Output formats for the assembled binary
+//!
+//! - `dump`: One line of `0x..` bytes per instruction (the default on stdout)
+//! - `tiny`: A Tiny container with a header, a checksum and the symbol and
+//!   debug sections
+//! - `raw`: The plain bytes (the default for `--bin`)
+//! - `ihex`: Intel HEX
+//! - `srec`: Motorola S-record (S1 records with a 16 bit address)
+//! - `readmemh`: A memory image for Verilog's `$readmemh`
+//! - `logisim`: A ROM image for Logisim (`v2.0 raw`)
+//! - `rust`: A Rust `const PROGRAM: [u8; N]`
+//! - `c`: A C header with a `uint8_t` array
+//! - `python`: A Python bytes literal
+//!
+//! The source code formats also contain a constant with the address of every
+//! label (except the ones generated by syntax extensions), like
+//! `LABEL_LOOP = 0x03`. A label after the last byte of a full program (at
+//! 256) isn't an address and is left out.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use assembler::parser::ast::Ident;
+use container::Container;
+use machine::{WordSize, MEMORY_SIZE};
+
+
+/// The number of data bytes per record/line in the textual formats
+const BYTES_PER_LINE: usize = 16;
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    Dump,
+    Tiny,
+    Raw,
+    IntelHex,
+    SRecord,
+    Readmemh,
+    Logisim,
+    Rust,
+    C,
+    Python
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "dump" => Ok(Format::Dump),
+            "tiny" => Ok(Format::Tiny),
+            "raw" => Ok(Format::Raw),
+            "ihex" => Ok(Format::IntelHex),
+            "srec" => Ok(Format::SRecord),
+            "readmemh" => Ok(Format::Readmemh),
+            "logisim" => Ok(Format::Logisim),
+            "rust" => Ok(Format::Rust),
+            "c" => Ok(Format::C),
+            "python" => Ok(Format::Python),
+            _ => Err(format!("unknown output format: {}", s))
+        }
+    }
+}
+
+
+/// Convert the binary (one `Vec` per instruction) into the given format.
+/// `debug` contains the source location of every instruction (if known).
+pub fn generate_output(binary: &[Vec<WordSize>], labels: &HashMap<Ident, u32>,
+                       debug: Option<String>, format: Format) -> Vec<u8> {
+    let bytes: Vec<WordSize> = binary.iter()
+        .flat_map(|stmt| stmt.iter().cloned())
+        .collect();
+
+    if format == Format::Tiny {
+        return container(bytes, labels, debug)
+    }
+
+    let labels = label_constants(labels);
+
+    let text = match format {
+        Format::Raw => return bytes,
+        Format::Tiny => unreachable!(),
+        Format::Dump => dump(binary),
+        Format::IntelHex => intel_hex(&bytes),
+        Format::SRecord => s_record(&bytes),
+        Format::Readmemh => readmemh(&bytes),
+        Format::Logisim => logisim(&bytes),
+        Format::Rust => rust(&bytes, &labels),
+        Format::C => c(&bytes, &labels),
+        Format::Python => python(&bytes, &labels)
+    };
+
+    text.into_bytes()
+}
+
+
+fn container(bytes: Vec<WordSize>, labels: &HashMap<Ident, u32>,
+             debug: Option<String>) -> Vec<u8> {
+    let mut symbols: Vec<(u32, &str)> = labels.iter()
+        .map(|(name, offset)| (*offset, name.as_str()))
+        .collect();
+    symbols.sort();
+
+    let mut container = Container::new(bytes);
+    if symbols.len() > 0 {
+        container.symbols = Some(symbols.iter()
+            .map(|&(offset, name)| format!("{} {}\n", name, offset))
+            .collect::<Vec<_>>()
+            .concat());
+    }
+    container.debug = debug;
+
+    container.to_bytes()
+}
+
+fn dump(binary: &[Vec<WordSize>]) -> String {
+    let mut text = String::new();
+
+    for stmt in binary.iter() {
+        for b in stmt.iter() {
+            text.push_str(&format!("{:#04x} ", *b));
+        }
+        text.push_str("\n");
+    }
+
+    text
+}
+
+fn intel_hex(bytes: &[WordSize]) -> String {
+    let mut text = String::new();
+
+    for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = i * BYTES_PER_LINE;
+
+        // Length, address (high and low byte), record type 00 (data) and data
+        let mut record = vec![chunk.len() as WordSize, (address >> 8) as WordSize,
+                              address as WordSize, 0x00];
+        record.extend(chunk.iter().cloned());
+
+        // The checksum is the two's complement of the sum of all bytes
+        let sum = record.iter().fold(0u32, |sum, b| sum + *b as u32);
+        record.push(((0x100 - sum % 0x100) % 0x100) as WordSize);
+
+        text.push_str(&format!(":{}\n", hex(&record)));
+    }
+
+    text.push_str(":00000001FF\n");  // End of file
+    text
+}
+
+fn s_record(bytes: &[WordSize]) -> String {
+    let mut text = String::new();
+
+    text.push_str("S0030000FC\n");  // Header without a name
+
+    for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = i * BYTES_PER_LINE;
+
+        // Byte count (address, data and checksum), address and data
+        let mut record = vec![(chunk.len() + 3) as WordSize, (address >> 8) as WordSize,
+                              address as WordSize];
+        record.extend(chunk.iter().cloned());
+
+        // The checksum is the one's complement of the sum of all bytes
+        let sum = record.iter().fold(0u32, |sum, b| sum + *b as u32);
+        record.push(!(sum % 0x100) as WordSize);
+
+        text.push_str(&format!("S1{}\n", hex(&record)));
+    }
+
+    text.push_str("S9030000FC\n");  // Start address 0
+    text
+}
+
+fn readmemh(bytes: &[WordSize]) -> String {
+    let mut text = String::new();
+
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        let line = chunk.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .connect(" ");
+
+        text.push_str(&format!("{}\n", line));
+    }
+
+    text
+}
+
+fn logisim(bytes: &[WordSize]) -> String {
+    let mut text = String::new();
+    text.push_str("v2.0 raw\n");
+
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        let line = chunk.iter()
+            .map(|b| format!("{:x}", b))
+            .collect::<Vec<_>>()
+            .connect(" ");
+
+        text.push_str(&format!("{}\n", line));
+    }
+
+    text
+}
+
+fn rust(bytes: &[WordSize], labels: &[(String, u32)]) -> String {
+    let mut text = String::new();
+
+    for &(ref name, offset) in labels.iter() {
+        text.push_str(&format!("pub const {}: u8 = {:#04x};\n", name, offset));
+    }
+    if labels.len() > 0 {
+        text.push_str("\n");
+    }
+
+    text.push_str(&format!("pub const PROGRAM: [u8; {}] = [\n", bytes.len()));
+    text.push_str(&array_items(bytes));
+    text.push_str("];\n");
+    text
+}
+
+fn c(bytes: &[WordSize], labels: &[(String, u32)]) -> String {
+    let mut text = String::new();
+    text.push_str("#ifndef TINY_PROGRAM_H\n#define TINY_PROGRAM_H\n\n#include <stdint.h>\n\n");
+
+    for &(ref name, offset) in labels.iter() {
+        text.push_str(&format!("#define {} {:#04x}\n", name, offset));
+    }
+    if labels.len() > 0 {
+        text.push_str("\n");
+    }
+
+    text.push_str(&format!("#define PROGRAM_SIZE {}\n\n", bytes.len()));
+    text.push_str("static const uint8_t program[PROGRAM_SIZE] = {\n");
+    text.push_str(&array_items(bytes));
+    text.push_str("};\n\n#endif\n");
+    text
+}
+
+fn python(bytes: &[WordSize], labels: &[(String, u32)]) -> String {
+    let mut text = String::new();
+
+    for &(ref name, offset) in labels.iter() {
+        text.push_str(&format!("{} = {:#04x}\n", name, offset));
+    }
+    if labels.len() > 0 {
+        text.push_str("\n");
+    }
+
+    text.push_str("PROGRAM = (\n");
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        let line = chunk.iter()
+            .map(|b| format!("\\x{:02x}", b))
+            .collect::<Vec<_>>()
+            .concat();
+
+        text.push_str(&format!("    b\"{}\"\n", line));
+    }
+    text.push_str(")\n");
+    text
+}
+
+/// Get the constant names and addresses of all labels written in the source
+fn label_constants(labels: &HashMap<Ident, u32>) -> Vec<(String, u32)> {
+    let mut constants: Vec<(String, u32)> = labels.iter()
+        .filter(|&(name, offset)| {
+            !name.as_str().contains(".") && (*offset as usize) < MEMORY_SIZE
+        })
+        .map(|(name, offset)| (format!("LABEL_{}", name.as_str().to_uppercase()), *offset))
+        .collect();
+
+    constants.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    constants
+}
+
+/// Format bytes as the items of an array literal (one line per 16 bytes)
+fn array_items(bytes: &[WordSize]) -> String {
+    let mut text = String::new();
+
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        let line = chunk.iter()
+            .map(|b| format!("{:#04x},", b))
+            .collect::<Vec<_>>()
+            .connect(" ");
+
+        text.push_str(&format!("    {}\n", line));
+    }
+
+    text
+}
+
+/// Format bytes as uppercase hex without separators
+fn hex(bytes: &[WordSize]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use assembler::parser::ast::Ident;
+    use container::Container;
+
+    use super::{generate_output, label_constants, Format};
+
+    fn output(format: Format) -> String {
+        let binary = vec![vec![0x08, 0x00, 0x01], vec![0xff]];
+
+        let mut labels = HashMap::new();
+        labels.insert(Ident("end".to_string()), 3);
+        labels.insert(Ident("if0.end".to_string()), 3);
+
+        String::from_utf8(generate_output(&binary, &labels, None, format)).unwrap()
+    }
+
+    #[test]
+    fn test_dump() {
+        assert_eq!(output(Format::Dump), "0x08 0x00 0x01 \n0xff \n");
+    }
+
+    #[test]
+    fn test_tiny() {
+        let binary = vec![vec![0x08, 0x00, 0x01], vec![0xff]];
+        let bytes = generate_output(&binary, &HashMap::new(), Some("0 main.asm:1\n".to_string()),
+                                    Format::Tiny);
+
+        let container = Container::from_bytes(&bytes).unwrap();
+        assert_eq!(container.code, vec![0x08, 0x00, 0x01, 0xff]);
+        assert_eq!(container.symbols, None);
+        assert_eq!(container.debug, Some("0 main.asm:1\n".to_string()));
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        assert_eq!(output(Format::IntelHex), ":04000000080001FFF4\n:00000001FF\n");
+    }
+
+    #[test]
+    fn test_s_record() {
+        assert_eq!(output(Format::SRecord), "S0030000FC\nS1070000080001FFF0\nS9030000FC\n");
+    }
+
+    #[test]
+    fn test_readmemh() {
+        assert_eq!(output(Format::Readmemh), "08 00 01 ff\n");
+    }
+
+    #[test]
+    fn test_logisim() {
+        assert_eq!(output(Format::Logisim), "v2.0 raw\n8 0 1 ff\n");
+    }
+
+    #[test]
+    fn test_rust() {
+        assert_eq!(output(Format::Rust),
+                   "pub const LABEL_END: u8 = 0x03;\n\n\
+                    pub const PROGRAM: [u8; 4] = [\n    0x08, 0x00, 0x01, 0xff,\n];\n");
+    }
+
+    #[test]
+    fn test_c() {
+        assert_eq!(output(Format::C),
+                   "#ifndef TINY_PROGRAM_H\n#define TINY_PROGRAM_H\n\n#include <stdint.h>\n\n\
+                    #define LABEL_END 0x03\n\n\
+                    #define PROGRAM_SIZE 4\n\n\
+                    static const uint8_t program[PROGRAM_SIZE] = {\n    0x08, 0x00, 0x01, 0xff,\n};\n\n\
+                    #endif\n");
+    }
+
+    #[test]
+    fn test_label_constants() {
+        let mut labels = HashMap::new();
+        labels.insert(Ident("start".to_string()), 0);
+        labels.insert(Ident("end".to_string()), 256);
+
+        // `end` points behind the last byte of the program memory
+        assert_eq!(label_constants(&labels), vec![("LABEL_START".to_string(), 0)]);
+    }
+
+    #[test]
+    fn test_python() {
+        assert_eq!(output(Format::Python),
+                   "LABEL_END = 0x03\n\nPROGRAM = (\n    b\"\\x08\\x00\\x01\\xff\"\n)\n");
+    }
+}
@@ -1,220 +1,530 @@
-//! The Tiny Abstract Syntax Tree.
-//! Modeled following the grammar (`grammar.md`). Every compound item has an
-//! `Item` enum with all options and an `ItemNode` which contains the item
-//! and the location in the source file.
-
-use std::borrow::ToOwned;
-use std::fmt;
-
-use assembler::parser::lexer::SourceLocation;
-use machine::Mnemonic as Instruction;  // FIXME
-use machine::WordSize;
-
-
-pub type Program = Vec<StatementNode>;
-
-
-// --- Helper for AST definitions -----------------------------------------------
-
-macro_rules! define(
-    ( $name:ident -> $wrapper:ident : $( $variants:ident ( $( $arg:ty ),* ) ),* ) => {
-        #[derive(PartialEq, Eq, Clone)]
-        pub struct $wrapper {
-            pub value: $name,
-            pub location: SourceLocation
-        }
-
-        impl_to_string!($wrapper: "{}", value);
-
-        #[derive(PartialEq, Eq, Clone)]
-        pub enum $name {
-            $( $variants ( $( $arg ),* ) ),*
-        }
-
-        impl $name {
-            pub fn new(stmt: $name, location: SourceLocation) -> $wrapper {
-                $wrapper {
-                    value: stmt,
-                    location: location
-                }
-            }
-        }
-    };
-);
-
-// --- AST: Compound items ------------------------------------------------------
-
-// --- AST: Compound items: Statements ------------------------------------------
-
-define!(Statement -> StatementNode:
-    Include(IPath),                         // Ex: #import <...>
-    Label(Ident),                           // Ex: label:
-    Const(Ident, ArgumentNode),         // Ex: $const = 2
-    Operation(Mnemonic, Vec<ArgumentNode>), // Ex: @macro(args, ...)
-    Macro(Ident, Vec<MacroArgumentNode>)
-);
-
-impl fmt::Debug for Statement {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Statement::Include(ref path) => write!(f, "#include {}", path),
-            Statement::Label(ref name)   => write!(f, "{}:", name),
-            Statement::Const(ref name, ref value) => {
-                write!(f, "${} = {}", name, value)
-            },
-            Statement::Operation(ref mnem, ref args) => {
-                try!(write!(f, "{}", mnem));
-                for arg in args.iter() {
-                    try!(write!(f, " {}", arg));
-                }
-                Ok(())
-            },
-            Statement::Macro(ref name, ref args) => {
-                write!(f, "@{}({})", name,
-                       args.iter()
-                           .map(|arg| format!("{}", arg))
-                           .collect::<Vec<_>>()
-                           .connect(" "))
-            }
-        }
-    }
-}
-
-impl fmt::Display for Statement {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-
-// --- AST: Compound items: Arguments -------------------------------------------
-
-define!(Argument -> ArgumentNode:
-    Literal(WordSize),            // A simple literal
-    Address(Option<WordSize>),    // An address (`[0]`) or an auto-filled address (`[_]`)
-    Const(Ident),           // A constant (`$const`)
-    Label(Ident),           // A label (`:label`)
-    Char(WordSize)                // A character (`'a'`)
-);
-
-impl fmt::Debug for Argument {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Argument::Literal(i) => write!(f, "{}", i),
-            Argument::Address(addr) => {
-                match addr {
-                    Some(i) => write!(f, "[{}]", i),
-                    None => write!(f, "[_]")
-                }
-            },
-            Argument::Const(ref name) => write!(f, "${}", name),
-            Argument::Label(ref name) => write!(f, ":{}", name),
-            Argument::Char(c) => write!(f, "'{}'", c),
-        }
-    }
-}
-
-impl fmt::Display for Argument {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-
-// --- AST: Compound items: Macro Arguments -------------------------------------
-
-define!(MacroArgument -> MacroArgumentNode:
-    Argument(ArgumentNode),
-    Ident(Ident)
-);
-
-impl fmt::Debug for MacroArgument {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            MacroArgument::Argument(ref arg) => write!(f, "{}", arg),
-            MacroArgument::Ident(ref name) => write!(f, "{}", name)
-        }
-    }
-}
-
-impl fmt::Display for MacroArgument {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-
-// --- AST: Single items --------------------------------------------------------
-
-// --- AST: Single items: Identifier --------------------------------------------
-
-#[derive(PartialEq, Eq, Hash, Clone)]
-pub struct Ident(pub String);
-
-impl Ident {
-    pub fn as_str(&self) -> &str {
-        let Ident(ref s) = *self;
-        s
-    }
-
-    pub fn clone(&self) -> Ident {
-        Ident(self.as_str().to_owned())
-    }
-}
-
-impl fmt::Debug for Ident {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.as_str())
-    }
-}
-
-impl fmt::Display for Ident {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-
-// --- AST: Single items: Mnemonic ----------------------------------------------
-
-#[derive(PartialEq, Eq, Clone)]
-pub struct Mnemonic(pub Instruction);
-
-impl fmt::Debug for Mnemonic {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Mnemonic(ref mnem) = *self;
-        write!(f, "{:?}", mnem)
-    }
-}
-
-impl fmt::Display for Mnemonic {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-
-// --- AST: Single items: Import Path -------------------------------------------
-
-#[derive(PartialEq, Eq, Clone)]
-pub struct IPath(pub String);
-
-impl IPath {
-    pub fn as_str(&self) -> &str {
-        let IPath(ref p) = *self;
-        &**p
-    }
-}
-
-impl fmt::Debug for IPath {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let IPath(ref path) = *self;
-        write!(f, "<{}>", path)
-    }
-}
-
-impl fmt::Display for IPath {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
+//! The Tiny Abstract Syntax Tree.
+//! Modeled following the grammar (`grammar.md`). Every compound item has an
+//! `Item` enum with all options and an `ItemNode` which contains the item
+//! and the location in the source file.
+
+use std::borrow::ToOwned;
+use std::fmt;
+use std::str::FromStr;
+
+use assembler::parser::lexer::SourceLocation;
+use machine::Mnemonic as Instruction;  // FIXME
+use machine::WordSize;
+
+
+pub type Program = Vec<StatementNode>;
+
+
+// --- Helper for AST definitions -----------------------------------------------
+
+macro_rules! define(
+    ( $name:ident -> $wrapper:ident : $( $variants:ident ( $( $arg:ty ),* ) ),* ) => {
+        #[derive(PartialEq, Eq, Clone)]
+        pub struct $wrapper {
+            pub value: $name,
+            pub location: SourceLocation
+        }
+
+        impl_to_string!($wrapper: "{}", value);
+
+        #[derive(PartialEq, Eq, Clone)]
+        pub enum $name {
+            $( $variants ( $( $arg ),* ) ),*
+        }
+
+        impl $name {
+            pub fn new(stmt: $name, location: SourceLocation) -> $wrapper {
+                $wrapper {
+                    value: stmt,
+                    location: location
+                }
+            }
+        }
+    };
+);
+
+// --- AST: Compound items ------------------------------------------------------
+
+// --- AST: Compound items: Statements ------------------------------------------
+
+define!(Statement -> StatementNode:
+    Include(IPath),                         // Ex: #import <...>
+    Directive(Directive),                   // Ex: #if $debug
+    Label(Ident),                           // Ex: label:
+    Const(Ident, ArgumentNode),         // Ex: $const = 2
+    Data(Ident, ArgumentNode, Vec<ArgumentNode>),   // Ex: $table = [_] = (1, 4, 9)
+    Operation(Mnemonic, Vec<ArgumentNode>), // Ex: MOV [0] 1
+    PseudoOp(PseudoMnemonic, Vec<ArgumentNode>),    // Ex: INC $a
+    Macro(Ident, Vec<MacroArgumentNode>, Vec<MacroArgumentNode>)  // Ex: @macro(args, ... -> results, ...)
+);
+
+impl Statement {
+    /// Get all arguments of this statement (including macro arguments and results)
+    pub fn arguments(&self) -> Vec<&ArgumentNode> {
+        match *self {
+            Statement::Const(_, ref arg) => vec![arg],
+            Statement::Data(_, ref arg, ref values) => {
+                Some(arg).into_iter().chain(values.iter()).collect()
+            },
+            Statement::Operation(_, ref args) => args.iter().collect(),
+            Statement::PseudoOp(_, ref args) => args.iter().collect(),
+            Statement::Macro(_, ref args, ref results) => {
+                let mut arguments = vec![];
+                for marg in args.iter().chain(results.iter()) {
+                    if let MacroArgument::Argument(ref arg) = marg.value {
+                        arguments.push(arg);
+                    }
+                }
+
+                arguments
+            },
+            _ => vec![]
+        }
+    }
+
+    /// Get the number of bytes this statement takes in the binary
+    pub fn size(&self) -> usize {
+        match *self {
+            Statement::Operation(_, ref args) => 1 + args.len(),
+            _ => 0
+        }
+    }
+
+    /// Get all arguments of this statement (including macro arguments and results)
+    pub fn arguments_mut(&mut self) -> Vec<&mut ArgumentNode> {
+        match *self {
+            Statement::Const(_, ref mut arg) => vec![arg],
+            Statement::Data(_, ref mut arg, ref mut values) => {
+                Some(arg).into_iter().chain(values.iter_mut()).collect()
+            },
+            Statement::Operation(_, ref mut args) => args.iter_mut().collect(),
+            Statement::PseudoOp(_, ref mut args) => args.iter_mut().collect(),
+            Statement::Macro(_, ref mut args, ref mut results) => {
+                let mut arguments = vec![];
+                for marg in args.iter_mut().chain(results.iter_mut()) {
+                    if let MacroArgument::Argument(ref mut arg) = marg.value {
+                        arguments.push(arg);
+                    }
+                }
+
+                arguments
+            },
+            _ => vec![]
+        }
+    }
+}
+
+impl fmt::Debug for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Statement::Include(ref path) => write!(f, "#include {}", path),
+            Statement::Directive(ref directive) => write!(f, "{}", directive),
+            Statement::Label(ref name)   => write!(f, "{}:", name),
+            Statement::Const(ref name, ref value) => {
+                write!(f, "${} = {}", name, value)
+            },
+            Statement::Data(ref name, ref value, ref values) => {
+                write!(f, "${} = {} = ({})", name, value,
+                       values.iter()
+                             .map(|arg| format!("{}", arg))
+                             .collect::<Vec<_>>()
+                             .connect(", "))
+            },
+            Statement::Operation(ref mnem, ref args) => {
+                try!(write!(f, "{}", mnem));
+                for arg in args.iter() {
+                    try!(write!(f, " {}", arg));
+                }
+                Ok(())
+            },
+            Statement::PseudoOp(ref mnem, ref args) => {
+                try!(write!(f, "{:?}", mnem));
+                for arg in args.iter() {
+                    try!(write!(f, " {}", arg));
+                }
+                Ok(())
+            },
+            Statement::Macro(ref name, ref args, ref results) => {
+                try!(write!(f, "@{}({}", name,
+                            args.iter()
+                                .map(|arg| format!("{}", arg))
+                                .collect::<Vec<_>>()
+                                .connect(" ")));
+                if results.len() > 0 {
+                    try!(write!(f, " -> {}",
+                                results.iter()
+                                       .map(|arg| format!("{}", arg))
+                                       .collect::<Vec<_>>()
+                                       .connect(" ")));
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Compound items: Directives ------------------------------------------
+
+#[derive(PartialEq, Eq, Clone)]
+pub enum Directive {
+    If(ExpressionNode),     // Ex: #if $level > 1
+    IfDef(Ident),           // Ex: #ifdef debug
+    Else,                   // Ex: #else
+    EndIf                   // Ex: #endif
+}
+
+impl fmt::Debug for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Directive::If(ref expr) => write!(f, "#if {}", expr),
+            Directive::IfDef(ref name) => write!(f, "#ifdef {}", name),
+            Directive::Else => write!(f, "#else"),
+            Directive::EndIf => write!(f, "#endif")
+        }
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Compound items: Expressions -----------------------------------------
+
+define!(Expression -> ExpressionNode:
+    Value(Argument),                                            // Ex: 2, $const
+    Unary(Operator, Box<ExpressionNode>),                       // Ex: !$debug
+    Binary(Operator, Box<ExpressionNode>, Box<ExpressionNode>)  // Ex: $level > 1
+);
+
+impl Expression {
+    /// Get all constants used in this expression
+    pub fn constants(&self) -> Vec<&Ident> {
+        match *self {
+            Expression::Value(ref value) => value.constants(),
+            Expression::Unary(_, ref operand) => operand.value.constants(),
+            Expression::Binary(_, ref lhs, ref rhs) => {
+                let mut constants = lhs.value.constants();
+                constants.extend(rhs.value.constants().into_iter());
+                constants
+            }
+        }
+    }
+
+    /// Get all constants used in this expression
+    pub fn constants_mut(&mut self) -> Vec<&mut Ident> {
+        match *self {
+            Expression::Value(ref mut value) => value.constants_mut(),
+            Expression::Unary(_, ref mut operand) => operand.value.constants_mut(),
+            Expression::Binary(_, ref mut lhs, ref mut rhs) => {
+                let mut constants = lhs.value.constants_mut();
+                constants.extend(rhs.value.constants_mut().into_iter());
+                constants
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Value(ref value) => write!(f, "{}", value),
+            Expression::Unary(ref op, ref expr) => write!(f, "{}{}", op, expr),
+            Expression::Binary(ref op, ref lhs, ref rhs) => {
+                write!(f, "({} {} {})", lhs, op, rhs)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Compound items: Arguments -------------------------------------------
+
+define!(Argument -> ArgumentNode:
+    Literal(WordSize),            // A simple literal
+    Address(Option<WordSize>),    // An address (`[0]`) or an auto-filled address (`[_]`)
+    Array(WordSize),              // Auto-filled contiguous addresses (`[_; 16]`)
+    Const(Ident),           // A constant (`$const`)
+    Label(Ident),           // A label (`:label`)
+    Char(WordSize),               // A character (`'a'`)
+    Expr(Box<ExpressionNode>)     // A constant expression (`($const + 1)`)
+);
+
+impl Argument {
+    /// Get all constants this argument refers to (including the ones in expressions)
+    pub fn constants(&self) -> Vec<&Ident> {
+        match *self {
+            Argument::Const(ref name) => vec![name],
+            Argument::Expr(ref expr) => expr.value.constants(),
+            _ => vec![]
+        }
+    }
+
+    /// Get all constants this argument refers to (including the ones in expressions)
+    pub fn constants_mut(&mut self) -> Vec<&mut Ident> {
+        match *self {
+            Argument::Const(ref mut name) => vec![name],
+            Argument::Expr(ref mut expr) => expr.value.constants_mut(),
+            _ => vec![]
+        }
+    }
+}
+
+impl fmt::Debug for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Argument::Literal(i) => write!(f, "{}", i),
+            Argument::Address(addr) => {
+                match addr {
+                    Some(i) => write!(f, "[{}]", i),
+                    None => write!(f, "[_]")
+                }
+            },
+            Argument::Array(size) => write!(f, "[_; {}]", size),
+            Argument::Const(ref name) => write!(f, "${}", name),
+            Argument::Label(ref name) => write!(f, ":{}", name),
+            Argument::Char(c) => write!(f, "'{}'", c),
+            Argument::Expr(ref expr) => {
+                match expr.value {
+                    Expression::Binary(..) => write!(f, "{}", expr),  // Already in parens
+                    _ => write!(f, "({})", expr)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Compound items: Macro Arguments -------------------------------------
+
+define!(MacroArgument -> MacroArgumentNode:
+    Argument(ArgumentNode),
+    Ident(Ident),
+    Str(String)     // Ex: "message"
+);
+
+impl fmt::Debug for MacroArgument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MacroArgument::Argument(ref arg) => write!(f, "{}", arg),
+            MacroArgument::Ident(ref name) => write!(f, "{}", name),
+            MacroArgument::Str(ref string) => write!(f, "\"{}\"", string)
+        }
+    }
+}
+
+impl fmt::Display for MacroArgument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Single items --------------------------------------------------------
+
+// --- AST: Single items: Identifier --------------------------------------------
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct Ident(pub String);
+
+impl Ident {
+    pub fn as_str(&self) -> &str {
+        let Ident(ref s) = *self;
+        s
+    }
+
+    pub fn clone(&self) -> Ident {
+        Ident(self.as_str().to_owned())
+    }
+}
+
+impl fmt::Debug for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Single items: Operator ----------------------------------------------
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Operator {
+    Add,
+    Sub,    // Also used for negation
+    Mul,
+    Div,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    And,
+    Or
+}
+
+impl fmt::Debug for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Not => "!",
+            Operator::Eq  => "==",
+            Operator::Ne  => "!=",
+            Operator::Lt  => "<",
+            Operator::Le  => "<=",
+            Operator::Gt  => ">",
+            Operator::Ge  => ">=",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+            Operator::And => "&&",
+            Operator::Or  => "||"
+        };
+
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Single items: Mnemonic ----------------------------------------------
+
+#[derive(PartialEq, Eq, Clone)]
+pub struct Mnemonic(pub Instruction);
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Mnemonic(ref mnem) = *self;
+        write!(f, "{:?}", mnem)
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+// --- AST: Single items: Pseudo Mnemonic ---------------------------------------
+
+/// An instruction the assembler expands to real instructions
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub enum PseudoMnemonic {
+    INC,        // INC a       -> ADD a 1
+    DEC,        // DEC a       -> SUB a 1
+    CLR,        // CLR a       -> MOV a 0
+    JNE,        // JNE a b c   -> Jump to a if b != c
+    JGE,        // JGE a b c   -> Jump to a if b >= c
+    JLE,        // JLE a b c   -> Jump to a if b <= c
+    JNZ,        // JNZ a b     -> Jump to a if b != 0
+    SWAP,       // SWAP a b    -> Exchange M[a] and M[b]
+    JLS_S,      // JLS.S a b c -> Jump to a if b < c (signed)
+    JGT_S,      // JGT.S a b c -> Jump to a if b > c (signed)
+    DPRINT_S    // DPRINT.S a  -> Print M[a] in decimal (signed)
+}
+
+impl FromStr for PseudoMnemonic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PseudoMnemonic, String> {
+        match s {
+            "INC"      => Ok(PseudoMnemonic::INC),
+            "DEC"      => Ok(PseudoMnemonic::DEC),
+            "CLR"      => Ok(PseudoMnemonic::CLR),
+            "JNE"      => Ok(PseudoMnemonic::JNE),
+            "JGE"      => Ok(PseudoMnemonic::JGE),
+            "JLE"      => Ok(PseudoMnemonic::JLE),
+            "JNZ"      => Ok(PseudoMnemonic::JNZ),
+            "SWAP"     => Ok(PseudoMnemonic::SWAP),
+            "JLS.S"    => Ok(PseudoMnemonic::JLS_S),
+            "JGT.S"    => Ok(PseudoMnemonic::JGT_S),
+            "DPRINT.S" => Ok(PseudoMnemonic::DPRINT_S),
+            _ => Err(format!("Invalid pseudo instruction: {}", s))
+        }
+    }
+}
+
+impl fmt::Debug for PseudoMnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            PseudoMnemonic::INC => "INC",
+            PseudoMnemonic::DEC => "DEC",
+            PseudoMnemonic::CLR => "CLR",
+            PseudoMnemonic::JNE => "JNE",
+            PseudoMnemonic::JGE => "JGE",
+            PseudoMnemonic::JLE => "JLE",
+            PseudoMnemonic::JNZ => "JNZ",
+            PseudoMnemonic::SWAP => "SWAP",
+            PseudoMnemonic::JLS_S => "JLS.S",
+            PseudoMnemonic::JGT_S => "JGT.S",
+            PseudoMnemonic::DPRINT_S => "DPRINT.S"
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+
+// --- AST: Single items: Import Path -------------------------------------------
+
+#[derive(PartialEq, Eq, Clone)]
+pub struct IPath(pub String);
+
+impl IPath {
+    pub fn as_str(&self) -> &str {
+        let IPath(ref p) = *self;
+        &**p
+    }
+}
+
+impl fmt::Debug for IPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let IPath(ref path) = *self;
+        write!(f, "<{}>", path)
+    }
+}
+
+impl fmt::Display for IPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
\ No newline at end of file
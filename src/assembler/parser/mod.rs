@@ -1,566 +1,979 @@
-//! The Parser
-//!
-//! A simple recursive descent parser the grammar as described in `grammar.md`.
-
-pub mod ast;
-mod lexer;
-mod syntax_ext;
-
-use std::borrow::ToOwned;
-use std::collections::LinkedList;
-use assembler::util::fatal;
-use self::ast::*;
-use self::lexer::{Lexer, FileLexer, Token};
-
-pub use self::lexer::{SourceLocation, dummy_source};
-pub use self::syntax_ext::expand_syntax_extensions;
-
-
-pub struct Parser<'a> {
-    location: SourceLocation,
-    token: Token<'a>,
-    buffer: LinkedList<Token<'a>>,
-    lexer: Box<Lexer<'a> + 'a>
-}
-
-impl<'a> Parser<'a> {
-    pub fn new(source: &'a str, file: &str) -> Parser<'a> {
-        Parser::with_lexer(Box::new(FileLexer::new(source, file)))
-    }
-
-    pub fn with_lexer(mut lx: Box<Lexer<'a> + 'a>) -> Parser {
-        Parser {
-            token: lx.next_token(),
-            location: lx.get_source(),
-            buffer: LinkedList::new(),
-            lexer: lx
-        }
-    }
-
-    pub fn parse(&mut self) -> Program {
-        let mut source = vec![];
-
-        debug!("Starting parsing");
-
-        while self.token != Token::EOF {
-            source.push(self.parse_statement());
-        }
-
-        debug!("Parsing finished");
-
-        source
-    }
-
-
-    // --- Error handling -------------------------------------------------------
-
-    fn fatal(&self, msg: String) -> ! {
-        fatal(msg, &self.location);
-    }
-
-    fn unexpected_token(&self, tok: &Token, expected: Option<&'static str>) -> ! {
-        match expected {
-            Some(ex) => self.fatal(format!("unexpected token: `{}`, expected {}", tok, ex)),
-            None => self.fatal(format!("unexpected token: `{}`", tok))
-        }
-    }
-
-
-    // --- Token processing -----------------------------------------------------
-
-    fn update_location(&mut self) -> SourceLocation {
-        self.location = self.lexer.get_source();
-        self.location.clone()
-    }
-
-    fn bump(&mut self) {
-        self.token = match self.buffer.pop_front() {
-            Some(tok) => tok,
-            None => self.lexer.next_token()
-        };
-    }
-
-    fn eat(&mut self, tok: &Token) -> bool {
-        if self.token == *tok {
-            self.bump();
-            true
-        } else {
-            false
-        }
-    }
-
-    fn expect(&mut self, tok: &Token) {
-        if !self.eat(tok) {
-            self.fatal(format!("expected `{}`, found `{}`", tok, self.token))
-        }
-    }
-
-    fn look_ahead<F, R>(&mut self, distance: usize, f: F) -> R where F: Fn(&Token) -> R {
-        if self.buffer.len() < distance {
-            for _ in 0 .. distance - self.buffer.len() {
-                self.buffer.push_back(self.lexer.next_token());
-            }
-        }
-
-        f(self.buffer.iter().nth(distance - 1).unwrap())
-    }
-
-    // --- Actual parsing -------------------------------------------------------
-
-    fn token_is_argument(&mut self) -> bool {
-        match self.token {
-            Token::INTEGER(_) | Token::CHAR(_)
-                | Token::LBRACKET | Token::COLON => true,
-            Token::DOLLAR => self.look_ahead(2, |t| return t != &Token::EQ),
-            _ => false
-        }
-    }
-
-    // --- Parsing: Single tokens -----------------------------------------------
-
-    fn parse_ident(&mut self) -> Ident {
-        let ident = match self.token {
-            Token::IDENT(id) => Ident(id.to_owned()),
-            _ => self.unexpected_token(&self.token, Some("a identifier"))
-        };
-        self.bump();
-
-        ident
-    }
-
-    fn parse_path(&mut self) -> IPath {
-        let path = match self.token {
-            Token::PATH(p) => IPath(p.to_owned()),
-            _ => self.unexpected_token(&self.token, Some("a path"))
-        };
-        self.bump();
-
-        path
-    }
-
-    // --- Parsing: Compound expressions ----------------------------------------
-
-    fn parse_address(&mut self) -> Option<u8> {
-        self.expect(&Token::LBRACKET);
-
-        let value = match self.token {
-            Token::INTEGER(i) => Some(i),
-            Token::UNDERSCORE => None,
-            _ => self.unexpected_token(&self.token, Some("an address"))
-        };
-        self.bump();
-
-        self.expect(&Token::RBRACKET);
-
-        value
-    }
-
-    fn parse_label(&mut self) -> Ident {
-        self.expect(&Token::COLON);
-        self.parse_ident()
-    }
-
-    fn parse_constant(&mut self) -> Ident {
-        self.expect(&Token::DOLLAR);
-        self.parse_ident()
-    }
-
-    fn parse_argument(&mut self) -> ArgumentNode {
-        let location = self.update_location();
-
-        let arg = match self.token {
-            Token::INTEGER(i) => { self.bump(); Argument::Literal(i) },
-            Token::CHAR(c)    => { self.bump(); Argument::Char(c) },
-            Token::LBRACKET   => Argument::Address(self.parse_address()),
-            Token::DOLLAR     => Argument::Const(self.parse_constant()),
-            Token::COLON      => Argument::Label(self.parse_label()),
-            _ => self.unexpected_token(&self.token, Some("an argument"))
-        };
-
-        Argument::new(arg, location)
-    }
-
-    fn parse_macro_argument(&mut self) -> MacroArgumentNode {
-        let location = self.update_location();
-
-        if self.token_is_argument() {
-            MacroArgument::new(MacroArgument::Argument(self.parse_argument()),
-                               location)
-        } else {
-            MacroArgument::new(MacroArgument::Ident(self.parse_ident()),
-                               location)
-        }
-    }
-
-    // ---- Parsing: Expressions ------------------------------------------------
-
-    fn parse_include(&mut self) -> StatementNode {
-        let location = self.update_location();
-
-        self.bump();
-        self.expect(&Token::IDENT("import"));
-        let path = self.parse_path();
-
-        Statement::new(Statement::Include(path), location)
-    }
-
-    fn parse_label_def(&mut self) -> StatementNode {
-        let location = self.update_location();
-
-        let label = self.parse_ident();
-        self.expect(&Token::COLON);
-
-        Statement::new(Statement::Label(label), location)
-    }
-
-    fn parse_constant_def(&mut self) -> StatementNode {
-        let location = self.update_location();
-
-        let name = self.parse_constant();
-        self.expect(&Token::EQ);
-        let value = self.parse_argument();
-
-        Statement::new(Statement::Const(name, value), location)
-    }
-
-    fn parse_operation(&mut self) -> StatementNode {
-        let location = self.update_location();
-
-        let mn = if let Token::MNEMONIC(mn) = self.token {
-            Mnemonic(mn)
-        } else {
-            self.unexpected_token(&self.token, Some("a mnemonic"))
-        };
-
-        self.bump();
-
-        let mut args = vec![];
-        while self.token_is_argument() {
-            args.push(self.parse_argument());
-        }
-
-        Statement::new(Statement::Operation(mn, args), location)
-    }
-
-    fn parse_macro(&mut self) -> StatementNode {
-        let location = self.update_location();
-
-        self.expect(&Token::AT);
-        let name = self.parse_ident();
-
-        self.expect(&Token::LPAREN);
-
-        let mut args = vec![];
-        if self.token != Token::RPAREN {
-            loop {
-                args.push(self.parse_macro_argument());
-                if !self.eat(&Token::COMMA) {
-                    break
-                }
-            }
-        }
-        self.expect(&Token::RPAREN);
-
-        Statement::new(Statement::Macro(name, args), location)
-    }
-
-    fn parse_statement(&mut self) -> StatementNode {
-        let stmt = match self.token {
-            Token::HASH        => self.parse_include(),
-            Token::DOLLAR      => self.parse_constant_def(),
-            Token::IDENT(_)    => self.parse_label_def(),
-            Token::MNEMONIC(_) => self.parse_operation(),
-            Token::AT          => self.parse_macro(),
-
-            ref tok => self.unexpected_token(tok, Some("a statement"))
-        };
-
-        stmt
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::borrow::ToOwned;
-    use std::rc::Rc;
-
-    use assembler::parser::ast::*;
-    use assembler::parser::lexer::{Token, Lexer};
-    use assembler::parser::lexer::Token::*;
-
-    use super::*;
-
-    fn parse<'a, F, T>(toks: Vec<Token<'a>>, f: F) -> T where F: Fn(&mut Parser<'a>) -> T {
-        f(&mut Parser::with_lexer(Box::new(toks) as Box<Lexer>))
-    }
-
-    fn ident_from_str(s: &str) -> Ident {
-        Ident(s.to_owned())
-    }
-
-    fn path_from_str(s: &str) -> IPath {
-        IPath(s.to_owned())
-    }
-
-    #[test]
-    fn test_statements() {
-        assert_eq!(
-            parse(
-                vec![HASH, IDENT("import"), PATH("as/d"),
-                     MNEMONIC("HALT".parse().unwrap())],
-                |p| p.parse()
-            ),
-            vec![
-                Statement::new(
-                    Statement::Include(
-                        path_from_str("as/d")
-                    ),
-                    dummy_source()
-                ),
-                Statement::new(
-                    Statement::Operation(
-                        Mnemonic("HALT".parse().unwrap()),
-                        vec![]
-                    ),
-                    dummy_source()
-                )
-            ]
-        )
-    }
-
-    #[test]
-    fn test_include() {
-        assert_eq!(
-            parse(vec![HASH, IDENT("import"), PATH("as/d")],
-                  |p| p.parse_statement()),
-            Statement::new(
-                Statement::Include(
-                    path_from_str("as/d")
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_label_def() {
-        assert_eq!(
-            parse(vec![IDENT("lbl"), COLON],
-                  |p| p.parse_statement()),
-            Statement::new(
-                Statement::Label(
-                    ident_from_str("lbl")
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_const_def() {
-        assert_eq!(
-            parse(vec![DOLLAR, IDENT("c"), EQ, INTEGER(0)],
-                  |p| p.parse_statement()),
-            Statement::new(
-                Statement::Const(
-                    ident_from_str("c"),
-                    Argument::new(
-                        Argument::Literal(0),
-                        dummy_source()
-                    )
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_operation() {
-        assert_eq!(
-            parse(vec![MNEMONIC("MOV".parse().unwrap()), INTEGER(0)],
-                  |p| p.parse_statement()),
-            Statement::new(
-                Statement::Operation(
-                    Mnemonic("MOV".parse().unwrap()),
-                    vec![
-                        Argument::new(
-                            Argument::Literal(0),
-                            dummy_source()
-                        )
-                    ]
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_macro() {
-        assert_eq!(
-            parse(vec![AT, IDENT("macro"),
-                       LPAREN, INTEGER(0), COMMA, INTEGER(0), RPAREN],
-                  |p| p.parse_statement()),
-            Statement::new(
-                Statement::Macro(
-                    ident_from_str("macro"),
-                    vec![
-                        MacroArgument::new(
-                            MacroArgument::Argument(
-                                Argument::new(
-                                    Argument::Literal(0),
-                                    dummy_source()
-                                )
-                            ),
-                            dummy_source()
-                        ),
-                        MacroArgument::new(
-                            MacroArgument::Argument(
-                                Argument::new(
-                                    Argument::Literal(0),
-                                    dummy_source()
-                                )
-                            ),
-                            dummy_source()
-                        )
-                    ]
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_literal() {
-        assert_eq!(
-            parse(vec![INTEGER(0)],
-                  |p| p.parse_argument()),
-            Argument::new(
-                Argument::Literal(0),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_address() {
-        assert_eq!(
-            parse(vec![LBRACKET, INTEGER(0), RBRACKET],
-                  |p| p.parse_argument()),
-            Argument::new(
-                Argument::Address(Some(0)),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_address_auto() {
-        assert_eq!(
-            parse(vec![LBRACKET, UNDERSCORE, RBRACKET],
-                  |p| p.parse_argument()),
-            Argument::new(
-                Argument::Address(None),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_const() {
-        assert_eq!(
-            parse(vec![DOLLAR, IDENT("asd")],
-                  |p| p.parse_argument()),
-            Argument::new(
-                Argument::Const(
-                    ident_from_str("asd")
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_label() {
-        assert_eq!(
-            parse(vec![COLON, IDENT("asd")],
-                  |p| p.parse_argument()),
-            Argument::new(
-                Argument::Label(
-                    ident_from_str("asd")
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_char() {
-        assert_eq!(
-            parse(vec![CHAR(0)],
-                  |p| p.parse_argument()),
-            Argument::new(
-                Argument::Char(0),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_macro_arg_arg() {
-        assert_eq!(
-            parse(vec![INTEGER(0)],
-                  |p| p.parse_macro_argument()),
-            MacroArgument::new(
-                MacroArgument::Argument(
-                    Argument::new(
-                        Argument::Literal(0),
-                        dummy_source()
-                    )
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_macro_arg_ident() {
-        assert_eq!(
-            parse(vec![IDENT("asd")],
-                  |p| p.parse_macro_argument()),
-            MacroArgument::new(
-                MacroArgument::Ident(
-                    ident_from_str("asd")
-                ),
-                dummy_source()
-            )
-        )
-    }
-
-    #[test]
-    fn test_op_and_const() {
-        assert_eq!(
-            parse(vec![MNEMONIC("HALT".parse().unwrap()),
-                       DOLLAR, IDENT("c"), EQ, INTEGER(0)],
-                  |p| p.parse()),
-            vec![
-                Statement::new(
-                    Statement::Operation(
-                        Mnemonic("HALT".parse().unwrap()),
-                        vec![]
-                    ),
-                    dummy_source()
-                ),
-                Statement::new(
-                    Statement::Const(
-                        ident_from_str("c"),
-                        Argument::new(
-                            Argument::Literal(0),
-                            dummy_source()
-                        )
-                    ),
-                    dummy_source()
-                )
-            ]
-        )
-    }
+//! The Parser
+//!
+//! A simple recursive descent parser the grammar as described in `grammar.md`.
+
+pub mod ast;
+mod lexer;
+mod syntax_ext;
+
+use std::borrow::ToOwned;
+use std::collections::LinkedList;
+use assembler::util::fatal;
+use self::ast::*;
+use self::lexer::{Lexer, FileLexer, Token};
+use machine::WordSize;
+
+pub use self::lexer::{SourceLocation, dummy_source};
+pub use self::syntax_ext::{expand_syntax_extensions, Context, Allocation, Relocation};
+#[cfg(test)]
+pub use self::syntax_ext::expand_source;
+
+
+pub struct Parser<'a> {
+    location: SourceLocation,
+    token: Token<'a>,
+    buffer: LinkedList<Token<'a>>,
+    lexer: Box<Lexer<'a> + 'a>
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str, file: &str) -> Parser<'a> {
+        Parser::with_lexer(Box::new(FileLexer::new(source, file)))
+    }
+
+    pub fn with_lexer(mut lx: Box<Lexer<'a> + 'a>) -> Parser {
+        Parser {
+            token: lx.next_token(),
+            location: lx.get_source(),
+            buffer: LinkedList::new(),
+            lexer: lx
+        }
+    }
+
+    pub fn parse(&mut self) -> Program {
+        let mut source = vec![];
+
+        debug!("Starting parsing");
+
+        while self.token != Token::EOF {
+            source.push(self.parse_statement());
+        }
+
+        debug!("Parsing finished");
+
+        source
+    }
+
+
+    // --- Error handling -------------------------------------------------------
+
+    fn fatal(&self, msg: String) -> ! {
+        fatal(msg, &self.location);
+    }
+
+    fn unexpected_token(&self, tok: &Token, expected: Option<&'static str>) -> ! {
+        match expected {
+            Some(ex) => self.fatal(format!("unexpected token: `{}`, expected {}", tok, ex)),
+            None => self.fatal(format!("unexpected token: `{}`", tok))
+        }
+    }
+
+
+    // --- Token processing -----------------------------------------------------
+
+    fn update_location(&mut self) -> SourceLocation {
+        self.location = self.lexer.get_source();
+        self.location.clone()
+    }
+
+    fn bump(&mut self) {
+        self.token = match self.buffer.pop_front() {
+            Some(tok) => tok,
+            None => self.lexer.next_token()
+        };
+    }
+
+    fn eat(&mut self, tok: &Token) -> bool {
+        if self.token == *tok {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &Token) {
+        if !self.eat(tok) {
+            self.fatal(format!("expected `{}`, found `{}`", tok, self.token))
+        }
+    }
+
+    fn look_ahead<F, R>(&mut self, distance: usize, f: F) -> R where F: Fn(&Token) -> R {
+        if self.buffer.len() < distance {
+            for _ in 0 .. distance - self.buffer.len() {
+                self.buffer.push_back(self.lexer.next_token());
+            }
+        }
+
+        f(self.buffer.iter().nth(distance - 1).unwrap())
+    }
+
+    // --- Actual parsing -------------------------------------------------------
+
+    fn token_is_argument(&mut self) -> bool {
+        match self.token {
+            Token::INTEGER(_) | Token::CHAR(_) | Token::MINUS
+                | Token::LBRACKET | Token::COLON | Token::LPAREN => true,
+            Token::DOLLAR => self.look_ahead(2, |t| return t != &Token::EQ),
+            _ => false
+        }
+    }
+
+    // --- Parsing: Single tokens -----------------------------------------------
+
+    fn parse_ident(&mut self) -> Ident {
+        let ident = match self.token {
+            Token::IDENT(id) => Ident(id.to_owned()),
+            _ => self.unexpected_token(&self.token, Some("a identifier"))
+        };
+        self.bump();
+
+        ident
+    }
+
+    fn parse_path(&mut self) -> IPath {
+        let path = match self.token {
+            Token::PATH(p) => IPath(p.to_owned()),
+            _ => self.unexpected_token(&self.token, Some("a path"))
+        };
+        self.bump();
+
+        path
+    }
+
+    // --- Parsing: Compound expressions ----------------------------------------
+
+    fn parse_address(&mut self) -> Argument {
+        self.expect(&Token::LBRACKET);
+
+        let value = match self.token {
+            Token::INTEGER(i) => Some(i),
+            Token::UNDERSCORE => None,
+            _ => self.unexpected_token(&self.token, Some("an address"))
+        };
+        self.bump();
+
+        // An array (`[_; size]`)
+        let arg = if self.eat(&Token::SEMICOLON) {
+            if value.is_some() {
+                self.fatal("arrays need an auto-filled address: [_; size]".to_owned())
+            }
+
+            let size = match self.token {
+                Token::INTEGER(0) => self.fatal("arrays need at least one element".to_owned()),
+                Token::INTEGER(i) => i,
+                _ => self.unexpected_token(&self.token, Some("an array size"))
+            };
+            self.bump();
+
+            Argument::Array(size)
+        } else {
+            Argument::Address(value)
+        };
+
+        self.expect(&Token::RBRACKET);
+
+        arg
+    }
+
+    fn parse_label(&mut self) -> Ident {
+        self.expect(&Token::COLON);
+        self.parse_ident()
+    }
+
+    fn parse_constant(&mut self) -> Ident {
+        self.expect(&Token::DOLLAR);
+        self.parse_ident()
+    }
+
+    fn parse_argument(&mut self) -> ArgumentNode {
+        let location = self.update_location();
+
+        let arg = match self.token {
+            Token::INTEGER(i) => { self.bump(); Argument::Literal(i) },
+            Token::CHAR(c)    => { self.bump(); Argument::Char(c) },
+            Token::MINUS      => {
+                // A negative literal (stored as two's complement)
+                self.bump();
+
+                let value = match self.token {
+                    Token::INTEGER(i) if i <= 128 => (256 - i as u16) as WordSize,
+                    Token::INTEGER(i) => self.fatal(format!("-{} doesn't fit into a byte", i)),
+                    _ => self.unexpected_token(&self.token, Some("an integer"))
+                };
+                self.bump();
+
+                Argument::Literal(value)
+            },
+            Token::LBRACKET   => self.parse_address(),
+            Token::DOLLAR     => Argument::Const(self.parse_constant()),
+            Token::COLON      => Argument::Label(self.parse_label()),
+            Token::LPAREN     => {
+                self.bump();
+                let expr = self.parse_expression();
+                self.expect(&Token::RPAREN);
+
+                Argument::Expr(Box::new(expr))
+            },
+            _ => self.unexpected_token(&self.token, Some("an argument"))
+        };
+
+        Argument::new(arg, location)
+    }
+
+    fn parse_macro_argument(&mut self) -> MacroArgumentNode {
+        let location = self.update_location();
+
+        if self.token_is_argument() || self.token == Token::NOT {
+            // Macro arguments can be whole expressions (like `@if($a == 2)`)
+            let expr = self.parse_expression();
+            let arg = match expr.value {
+                Expression::Value(arg) => arg,
+                value => Argument::Expr(Box::new(Expression::new(value, expr.location)))
+            };
+
+            MacroArgument::new(MacroArgument::Argument(Argument::new(arg, location.clone())),
+                               location)
+        } else if let Token::STRING(string) = self.token {
+            self.bump();
+            MacroArgument::new(MacroArgument::Str(string.to_owned()), location)
+        } else {
+            MacroArgument::new(MacroArgument::Ident(self.parse_ident()),
+                               location)
+        }
+    }
+
+    // --- Parsing: Constant expressions ----------------------------------------
+    // Operator precedence (lowest first): `||`, `&&`, comparisons, `<< >>`,
+    // `+ -`, `* /`, unary `! -`
+
+    fn parse_expression(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+        let mut lhs = self.parse_and_expression();
+
+        while self.eat(&Token::OROR) {
+            let rhs = self.parse_and_expression();
+            lhs = Expression::new(Expression::Binary(Operator::Or, Box::new(lhs), Box::new(rhs)),
+                                  location.clone());
+        }
+
+        lhs
+    }
+
+    fn parse_and_expression(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+        let mut lhs = self.parse_comparison();
+
+        while self.eat(&Token::ANDAND) {
+            let rhs = self.parse_comparison();
+            lhs = Expression::new(Expression::Binary(Operator::And, Box::new(lhs), Box::new(rhs)),
+                                  location.clone());
+        }
+
+        lhs
+    }
+
+    fn parse_comparison(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+        let lhs = self.parse_shift();
+
+        let op = match self.token {
+            Token::EQEQ => Operator::Eq,
+            Token::NE   => Operator::Ne,
+            Token::LT   => Operator::Lt,
+            Token::LE   => Operator::Le,
+            Token::GT   => Operator::Gt,
+            Token::GE   => Operator::Ge,
+            _ => return lhs
+        };
+        self.bump();
+
+        let rhs = self.parse_shift();
+        Expression::new(Expression::Binary(op, Box::new(lhs), Box::new(rhs)), location)
+    }
+
+    fn parse_shift(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+        let mut lhs = self.parse_sum();
+
+        loop {
+            let op = match self.token {
+                Token::SHL => Operator::Shl,
+                Token::SHR => Operator::Shr,
+                _ => return lhs
+            };
+            self.bump();
+
+            let rhs = self.parse_sum();
+            lhs = Expression::new(Expression::Binary(op, Box::new(lhs), Box::new(rhs)),
+                                  location.clone());
+        }
+    }
+
+    fn parse_sum(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+        let mut lhs = self.parse_product();
+
+        loop {
+            let op = match self.token {
+                Token::PLUS  => Operator::Add,
+                Token::MINUS => Operator::Sub,
+                _ => return lhs
+            };
+            self.bump();
+
+            let rhs = self.parse_product();
+            lhs = Expression::new(Expression::Binary(op, Box::new(lhs), Box::new(rhs)),
+                                  location.clone());
+        }
+    }
+
+    fn parse_product(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+        let mut lhs = self.parse_unary();
+
+        loop {
+            let op = match self.token {
+                Token::STAR  => Operator::Mul,
+                Token::SLASH => Operator::Div,
+                _ => return lhs
+            };
+            self.bump();
+
+            let rhs = self.parse_unary();
+            lhs = Expression::new(Expression::Binary(op, Box::new(lhs), Box::new(rhs)),
+                                  location.clone());
+        }
+    }
+
+    fn parse_unary(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+
+        let op = match self.token {
+            Token::NOT   => Operator::Not,
+            Token::MINUS => Operator::Sub,
+            _ => return self.parse_primary()
+        };
+        self.bump();
+
+        let expr = self.parse_unary();
+        Expression::new(Expression::Unary(op, Box::new(expr)), location)
+    }
+
+    fn parse_primary(&mut self) -> ExpressionNode {
+        let location = self.update_location();
+
+        if self.eat(&Token::LPAREN) {
+            let expr = self.parse_expression();
+            self.expect(&Token::RPAREN);
+
+            expr
+        } else {
+            let arg = self.parse_argument();
+            Expression::new(Expression::Value(arg.value), location)
+        }
+    }
+
+    // ---- Parsing: Expressions ------------------------------------------------
+
+    fn parse_directive(&mut self) -> StatementNode {
+        let location = self.update_location();
+
+        self.expect(&Token::HASH);
+        let name = self.parse_ident();
+
+        let directive = match name.as_str() {
+            "import" => {
+                let path = self.parse_path();
+                return Statement::new(Statement::Include(path), location)
+            },
+            "if"    => Directive::If(self.parse_expression()),
+            "ifdef" => Directive::IfDef(self.parse_ident()),
+            "else"  => Directive::Else,
+            "endif" => Directive::EndIf,
+            _ => self.fatal(format!("unknown directive: #{}", name))
+        };
+
+        Statement::new(Statement::Directive(directive), location)
+    }
+
+    fn parse_label_def(&mut self) -> StatementNode {
+        let location = self.update_location();
+
+        let label = self.parse_ident();
+        self.expect(&Token::COLON);
+
+        Statement::new(Statement::Label(label), location)
+    }
+
+    fn parse_constant_def(&mut self) -> StatementNode {
+        let location = self.update_location();
+
+        let name = self.parse_constant();
+        self.expect(&Token::EQ);
+        let value = self.parse_argument();
+
+        // Initialized data (`$table = [_] = (1, 4, 9)`)
+        if self.eat(&Token::EQ) {
+            self.expect(&Token::LPAREN);
+
+            let mut values = vec![self.parse_argument()];
+            while self.eat(&Token::COMMA) {
+                values.push(self.parse_argument());
+            }
+
+            self.expect(&Token::RPAREN);
+
+            return Statement::new(Statement::Data(name, value, values), location)
+        }
+
+        Statement::new(Statement::Const(name, value), location)
+    }
+
+    fn parse_operation(&mut self) -> StatementNode {
+        let location = self.update_location();
+
+        let token = self.token.clone();
+        match token {
+            Token::MNEMONIC(_) | Token::PSEUDO(_) => self.bump(),
+            _ => self.unexpected_token(&self.token, Some("a mnemonic"))
+        }
+
+        let mut args = vec![];
+        while self.token_is_argument() {
+            args.push(self.parse_argument());
+        }
+
+        let stmt = match token {
+            Token::PSEUDO(mn) => Statement::PseudoOp(mn, args),
+            Token::MNEMONIC(mn) => Statement::Operation(Mnemonic(mn), args),
+            _ => unreachable!()
+        };
+
+        Statement::new(stmt, location)
+    }
+
+    fn parse_macro(&mut self) -> StatementNode {
+        let location = self.update_location();
+
+        self.expect(&Token::AT);
+        let name = self.parse_ident();
+
+        self.expect(&Token::LPAREN);
+
+        let mut args = vec![];
+        if self.token != Token::RPAREN && self.token != Token::ARROW {
+            args = self.parse_macro_arguments();
+        }
+
+        let mut results = vec![];
+        if self.eat(&Token::ARROW) {
+            results = self.parse_macro_arguments();
+        }
+        self.expect(&Token::RPAREN);
+
+        Statement::new(Statement::Macro(name, args, results), location)
+    }
+
+    fn parse_macro_arguments(&mut self) -> Vec<MacroArgumentNode> {
+        let mut args = vec![];
+
+        loop {
+            args.push(self.parse_macro_argument());
+            if !self.eat(&Token::COMMA) {
+                break
+            }
+        }
+
+        args
+    }
+
+    fn parse_statement(&mut self) -> StatementNode {
+        let stmt = match self.token {
+            Token::HASH        => self.parse_directive(),
+            Token::DOLLAR      => self.parse_constant_def(),
+            Token::IDENT(_)    => self.parse_label_def(),
+            Token::MNEMONIC(_) | Token::PSEUDO(_) => self.parse_operation(),
+            Token::AT          => self.parse_macro(),
+
+            ref tok => self.unexpected_token(tok, Some("a statement"))
+        };
+
+        stmt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::ToOwned;
+    use std::rc::Rc;
+
+    use assembler::parser::ast::*;
+    use assembler::parser::lexer::{Token, Lexer};
+    use assembler::parser::lexer::Token::*;
+
+    use super::*;
+
+    fn parse<'a, F, T>(toks: Vec<Token<'a>>, f: F) -> T where F: Fn(&mut Parser<'a>) -> T {
+        f(&mut Parser::with_lexer(Box::new(toks) as Box<Lexer>))
+    }
+
+    fn ident_from_str(s: &str) -> Ident {
+        Ident(s.to_owned())
+    }
+
+    fn path_from_str(s: &str) -> IPath {
+        IPath(s.to_owned())
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(
+            parse(
+                vec![HASH, IDENT("import"), PATH("as/d"),
+                     MNEMONIC("HALT".parse().unwrap())],
+                |p| p.parse()
+            ),
+            vec![
+                Statement::new(
+                    Statement::Include(
+                        path_from_str("as/d")
+                    ),
+                    dummy_source()
+                ),
+                Statement::new(
+                    Statement::Operation(
+                        Mnemonic("HALT".parse().unwrap()),
+                        vec![]
+                    ),
+                    dummy_source()
+                )
+            ]
+        )
+    }
+
+    #[test]
+    fn test_include() {
+        assert_eq!(
+            parse(vec![HASH, IDENT("import"), PATH("as/d")],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Include(
+                    path_from_str("as/d")
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_label_def() {
+        assert_eq!(
+            parse(vec![IDENT("lbl"), COLON],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Label(
+                    ident_from_str("lbl")
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_const_def() {
+        assert_eq!(
+            parse(vec![DOLLAR, IDENT("c"), EQ, INTEGER(0)],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Const(
+                    ident_from_str("c"),
+                    Argument::new(
+                        Argument::Literal(0),
+                        dummy_source()
+                    )
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_data_def() {
+        assert_eq!(
+            parse(vec![DOLLAR, IDENT("t"), EQ, LBRACKET, UNDERSCORE, SEMICOLON, INTEGER(4), RBRACKET,
+                       EQ, LPAREN, INTEGER(1), COMMA, CHAR(2), RPAREN],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Data(
+                    ident_from_str("t"),
+                    Argument::new(
+                        Argument::Array(4),
+                        dummy_source()
+                    ),
+                    vec![
+                        Argument::new(Argument::Literal(1), dummy_source()),
+                        Argument::new(Argument::Char(2), dummy_source())
+                    ]
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_operation() {
+        assert_eq!(
+            parse(vec![MNEMONIC("MOV".parse().unwrap()), INTEGER(0)],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Operation(
+                    Mnemonic("MOV".parse().unwrap()),
+                    vec![
+                        Argument::new(
+                            Argument::Literal(0),
+                            dummy_source()
+                        )
+                    ]
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_pseudo_op() {
+        assert_eq!(
+            parse(vec![PSEUDO(PseudoMnemonic::INC), DOLLAR, IDENT("a")],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::PseudoOp(
+                    PseudoMnemonic::INC,
+                    vec![
+                        Argument::new(
+                            Argument::Const(ident_from_str("a")),
+                            dummy_source()
+                        )
+                    ]
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_macro() {
+        assert_eq!(
+            parse(vec![AT, IDENT("macro"),
+                       LPAREN, INTEGER(0), COMMA, INTEGER(0), RPAREN],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Macro(
+                    ident_from_str("macro"),
+                    vec![
+                        MacroArgument::new(
+                            MacroArgument::Argument(
+                                Argument::new(
+                                    Argument::Literal(0),
+                                    dummy_source()
+                                )
+                            ),
+                            dummy_source()
+                        ),
+                        MacroArgument::new(
+                            MacroArgument::Argument(
+                                Argument::new(
+                                    Argument::Literal(0),
+                                    dummy_source()
+                                )
+                            ),
+                            dummy_source()
+                        )
+                    ],
+                    vec![]
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_macro_results() {
+        assert_eq!(
+            parse(vec![AT, IDENT("macro"),
+                       LPAREN, INTEGER(0), ARROW, IDENT("a"), COMMA, IDENT("b"), RPAREN],
+                  |p| p.parse_statement()),
+            Statement::new(
+                Statement::Macro(
+                    ident_from_str("macro"),
+                    vec![
+                        MacroArgument::new(
+                            MacroArgument::Argument(
+                                Argument::new(
+                                    Argument::Literal(0),
+                                    dummy_source()
+                                )
+                            ),
+                            dummy_source()
+                        )
+                    ],
+                    vec![
+                        MacroArgument::new(
+                            MacroArgument::Ident(
+                                ident_from_str("a")
+                            ),
+                            dummy_source()
+                        ),
+                        MacroArgument::new(
+                            MacroArgument::Ident(
+                                ident_from_str("b")
+                            ),
+                            dummy_source()
+                        )
+                    ]
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_directive() {
+        assert_eq!(
+            parse(vec![HASH, IDENT("ifdef"), IDENT("debug"), HASH, IDENT("endif")],
+                  |p| p.parse()),
+            vec![
+                Statement::new(
+                    Statement::Directive(
+                        Directive::IfDef(ident_from_str("debug"))
+                    ),
+                    dummy_source()
+                ),
+                Statement::new(
+                    Statement::Directive(
+                        Directive::EndIf
+                    ),
+                    dummy_source()
+                )
+            ]
+        )
+    }
+
+    #[test]
+    fn test_expression() {
+        let value = |arg| Box::new(Expression::new(Expression::Value(arg), dummy_source()));
+
+        // 1 + 2 * 3 == $a
+        assert_eq!(
+            parse(vec![INTEGER(1), PLUS, INTEGER(2), STAR, INTEGER(3), EQEQ, DOLLAR, IDENT("a")],
+                  |p| p.parse_expression()),
+            Expression::new(
+                Expression::Binary(
+                    Operator::Eq,
+                    Box::new(Expression::new(
+                        Expression::Binary(
+                            Operator::Add,
+                            value(Argument::Literal(1)),
+                            Box::new(Expression::new(
+                                Expression::Binary(
+                                    Operator::Mul,
+                                    value(Argument::Literal(2)),
+                                    value(Argument::Literal(3))
+                                ),
+                                dummy_source()
+                            ))
+                        ),
+                        dummy_source()
+                    )),
+                    value(Argument::Const(ident_from_str("a")))
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(
+            parse(vec![INTEGER(0)],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Literal(0),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_negative_literal() {
+        assert_eq!(
+            parse(vec![MINUS, INTEGER(2)],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Literal(254),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(
+            parse(vec![LBRACKET, INTEGER(0), RBRACKET],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Address(Some(0)),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_address_auto() {
+        assert_eq!(
+            parse(vec![LBRACKET, UNDERSCORE, RBRACKET],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Address(None),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_const() {
+        assert_eq!(
+            parse(vec![DOLLAR, IDENT("asd")],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Const(
+                    ident_from_str("asd")
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_label() {
+        assert_eq!(
+            parse(vec![COLON, IDENT("asd")],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Label(
+                    ident_from_str("asd")
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_char() {
+        assert_eq!(
+            parse(vec![CHAR(0)],
+                  |p| p.parse_argument()),
+            Argument::new(
+                Argument::Char(0),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_macro_arg_arg() {
+        assert_eq!(
+            parse(vec![INTEGER(0)],
+                  |p| p.parse_macro_argument()),
+            MacroArgument::new(
+                MacroArgument::Argument(
+                    Argument::new(
+                        Argument::Literal(0),
+                        dummy_source()
+                    )
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_macro_arg_expression() {
+        let value = |arg| Box::new(Expression::new(Expression::Value(arg), dummy_source()));
+
+        assert_eq!(
+            parse(vec![DOLLAR, IDENT("i"), LT, INTEGER(10)],
+                  |p| p.parse_macro_argument()),
+            MacroArgument::new(
+                MacroArgument::Argument(
+                    Argument::new(
+                        Argument::Expr(Box::new(Expression::new(
+                            Expression::Binary(
+                                Operator::Lt,
+                                value(Argument::Const(ident_from_str("i"))),
+                                value(Argument::Literal(10))
+                            ),
+                            dummy_source()
+                        ))),
+                        dummy_source()
+                    )
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_macro_arg_ident() {
+        assert_eq!(
+            parse(vec![IDENT("asd")],
+                  |p| p.parse_macro_argument()),
+            MacroArgument::new(
+                MacroArgument::Ident(
+                    ident_from_str("asd")
+                ),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_macro_arg_string() {
+        assert_eq!(
+            parse(vec![STRING("message")],
+                  |p| p.parse_macro_argument()),
+            MacroArgument::new(
+                MacroArgument::Str("message".to_owned()),
+                dummy_source()
+            )
+        )
+    }
+
+    #[test]
+    fn test_op_and_const() {
+        assert_eq!(
+            parse(vec![MNEMONIC("HALT".parse().unwrap()),
+                       DOLLAR, IDENT("c"), EQ, INTEGER(0)],
+                  |p| p.parse()),
+            vec![
+                Statement::new(
+                    Statement::Operation(
+                        Mnemonic("HALT".parse().unwrap()),
+                        vec![]
+                    ),
+                    dummy_source()
+                ),
+                Statement::new(
+                    Statement::Const(
+                        ident_from_str("c"),
+                        Argument::new(
+                            Argument::Literal(0),
+                            dummy_source()
+                        )
+                    ),
+                    dummy_source()
+                )
+            ]
+        )
+    }
 }
\ No newline at end of file
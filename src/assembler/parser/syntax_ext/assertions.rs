@@ -0,0 +1,137 @@
+//! A syntax extension for compile-time assertions
+//!
+//! # Example:
+//!
+//! ```
+//! $table = [_; 8]
+//! @assert($table + 8 <= 64, "the table has to be below address 64")
+//! @assert(:main_loop < 256)
+//! @assert($program_size <= 200, "the program is too large")
+//! ```
+//!
+//! # Note:
+//!
+//! Assertions are checked after all other extensions have been expanded.
+//! Thus they can use the addresses of auto-allocated constants, the offsets
+//! of labels and the size of the final program in bytes (`$program_size`).
+
+use assembler::parser::ast::{Program, Statement, MacroArgument, Expression, Ident};
+use assembler::parser::syntax_ext::Context;
+use assembler::parser::syntax_ext::constants::value_of;
+use assembler::parser::syntax_ext::expressions::evaluate_with_labels;
+use assembler::util::fatal;
+
+
+pub fn expand(source: &mut Program, ctx: &Context) {
+    let program_size = source.iter().fold(0, |size, stmt| size + stmt.value.size());
+
+    source.retain(|stmt| {
+        let args = match stmt.value {
+            Statement::Macro(ref name, ref args, _) if name.as_str() == "assert" => args,
+            _ => return true  // Not an assertion, keep it
+        };
+
+        if args.len() != 1 && args.len() != 2 {
+            fatal!("expected (condition) or (condition, message), found {} args", args.len(); stmt)
+        }
+
+        let expr = match args[0].value {
+            MacroArgument::Argument(ref arg) => {
+                Expression::new(Expression::Value(arg.value.clone()), arg.location.clone())
+            },
+            ref other => fatal!("expected condition, found `{}`", other; args[0])
+        };
+
+        let message = if args.len() == 2 {
+            match args[1].value {
+                MacroArgument::Str(ref message) => message.clone(),
+                ref other => fatal!("expected message, found `{}`", other; args[1])
+            }
+        } else {
+            format!("{}", args[0])
+        };
+
+        let location = stmt.location.clone();
+        let value = evaluate_with_labels(&expr, &|name: &Ident| {
+            match value_of(name, &ctx.constants, &location) {
+                None if name.as_str() == "program_size" => Some(program_size as i64),
+                value => value
+            }
+        }, &|name: &Ident| {
+            match ctx.labels.get(name) {
+                Some(offset) => Some(*offset as i64),
+                None => fatal(format!("unknown label: {}", name), &location)
+            }
+        });
+
+        if value == 0 {
+            fatal!("assertion failed: {}", message; stmt)
+        }
+
+        false  // Remove the assertion from the source
+    });
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::ast::Statement;
+    use assembler::parser::syntax_ext::expand_source;
+
+    #[test]
+    fn test_assertions() {
+        let (program, _) = expand_source("$x = 5\n\
+                                          $y = [_]\n\
+                                          MOV $y $x\n\
+                                          end:\n\
+                                          HALT\n\
+                                          @assert($x == 5)\n\
+                                          @assert($y < 256, \"address out of range\")\n\
+                                          @assert(:end < 256)\n\
+                                          @assert(:end == 3)\n\
+                                          @assert($program_size == 4)\n");
+
+        // The assertions hold and are removed
+        assert_eq!(program.len(), 2);
+        assert!(program.iter().all(|stmt| match stmt.value {
+            Statement::Macro(..) => false,
+            _ => true
+        }));
+    }
+
+    #[test]
+    fn test_subroutine_assertions() {
+        let (program, _) = expand_source("@call(f)\n\
+                                          HALT\n\
+                                          @start(f, 0)\n\
+                                          MOV $return 1\n\
+                                          @assert($return < 256)\n\
+                                          @end()\n\
+                                          @assert(:f == 6)\n");
+
+        assert!(program.iter().all(|stmt| match stmt.value {
+            Statement::Macro(..) => false,
+            _ => true
+        }));
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_assertion_with_subroutines() {
+        // The subroutine extension must leave the assertions alone
+        expand_source("@call(f)\n\
+                       HALT\n\
+                       @start(f, 0)\n\
+                       MOV $return 1\n\
+                       @end()\n\
+                       @assert(:f == 0, \"f is at the start\")\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_failed_assertion() {
+        expand_source("MOV [0] 1\n\
+                       HALT\n\
+                       @assert($program_size < 4, \"the program is too large\")\n");
+    }
+}
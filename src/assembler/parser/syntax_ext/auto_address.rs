@@ -1,63 +1,298 @@
-//! A syntax extension that auto-fills addresses to prevent repeating and
-//! having to keeping track of memory addresses.
-//!
-//! # Example:
-//!
-//! ```
-//! $const = [_]
-//! MOV $const 2
-//! ```
-//!
-//! Results in:
-//!
-//! ```
-//! MOV [0] 2
-//! ```
-
-use assembler::parser::ast::{Program, Statement, Argument, ArgumentNode, MacroArgument};
-
-
-pub fn expand(source: &mut Program) {
-    // The address to use next
-    let mut auto_addr = 0u8;
-
-    // A helper function that replaces the value of the current argument
-    // with the next free address.
-    let mut update_arg = |arg: &mut ArgumentNode| {
-        if let Argument::Address(addr) = arg.value {
-            if addr == None {
-                arg.value = Argument::Address(Some(auto_addr));
-                auto_addr += 1;
-            }
-        }
-    };
-
-    // Process all statements in the current source
-    for stmt in source.iter_mut() {
-        match stmt.value {
-
-            // Process operation arguments
-            Statement::Operation(_, ref mut args) => {
-                for arg in args.iter_mut() {
-                    update_arg(arg);
-                }
-            },
-
-            // Process constants
-            Statement::Const(_, ref mut arg) => {
-                update_arg(arg);
-            },
-
-            // Process macro arguments
-            Statement::Macro(_, ref mut margs) => {
-                for marg in margs.iter_mut() {
-                    if let MacroArgument::Argument(ref mut arg) = marg.value {
-                        update_arg(arg);
-                    }
-                }
-            }
-
-            _ => {}
-        }
-    }
-}
\ No newline at end of file
+//! A syntax extension that auto-fills addresses to prevent repeating and
+//! having to keeping track of memory addresses.
+//!
+//! # Example:
+//!
+//! ```
+//! $const = [_]
+//! MOV $const 2
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! MOV [0] 2
+//! ```
+//!
+//! # Note:
+//!
+//! Addresses that are used explicitly (like `[5]`) are never handed out.
+//!
+//! Arrays (`[_; 16]`) get a block of contiguous addresses.
+//!
+//! The local variables of subroutines are placed after all other constants.
+//! Subroutines that can't be active at the same time share their addresses.
+//!
+//! In object files, the auto-filled addresses are relative to the start of the
+//! object's data. The linker moves them around the explicitly used addresses
+//! of all objects.
+
+use std::collections::HashMap;
+use assembler::parser::ast::{Program, Statement, Argument, Ident};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::{Context, Frame};
+use machine::{WordSize, MEMORY_SIZE};
+
+
+/// A memory address that's in use (either auto-filled or explicitly used)
+pub struct Allocation {
+    pub address: WordSize,
+    pub symbol: Option<Ident>,      // The constant the address is assigned to
+    pub size: usize,                // Number of bytes (for arrays)
+    pub location: SourceLocation,
+    pub explicit: bool
+}
+
+
+pub fn expand(source: &mut Program, ctx: &mut Context) {
+    let mut allocations = vec![];
+    let mut reserved = [false; MEMORY_SIZE];
+    let mut explicit = [false; MEMORY_SIZE];
+
+    // The sizes of all auto-filled constants
+    let mut sizes = HashMap::new();
+    for stmt in source.iter() {
+        if let Statement::Const(ref name, ref value) = stmt.value {
+            if let Some(size) = auto_size(&value.value) {
+                sizes.insert(name.clone(), size);
+            }
+        }
+    }
+
+    // Maps the local variables of subroutines to their frame and offset
+    let mut locals = HashMap::new();
+    let mut frame_sizes = HashMap::new();
+    for frame in ctx.frames.iter() {
+        let mut offset = 0;
+        for local in frame.locals.iter() {
+            locals.insert(local.clone(), (frame.routine.clone(), offset));
+            offset += *sizes.get(local).unwrap();
+        }
+
+        frame_sizes.insert(frame.routine.clone(), offset);
+    }
+
+    // Pass 1: Reserve all explicitly used addresses (named ones first so
+    // they show up with their name in the memory map)
+    {
+        let consts = source.iter().filter(|stmt| match stmt.value {
+            Statement::Const(..) => true,
+            _ => false
+        });
+        let others = source.iter().filter(|stmt| match stmt.value {
+            Statement::Const(..) => false,
+            _ => true
+        });
+
+        for stmt in consts.chain(others) {
+            let symbol = match stmt.value {
+                Statement::Const(ref name, _) => Some(name.clone()),
+                _ => None
+            };
+
+            for arg in stmt.value.arguments().into_iter() {
+                if let Argument::Address(Some(addr)) = arg.value {
+                    if !explicit[addr as usize] {
+                        explicit[addr as usize] = true;
+                        reserved[addr as usize] = !ctx.object;  // The linker takes care of it
+
+                        allocations.push(Allocation {
+                            address: addr,
+                            symbol: symbol.clone(),
+                            size: 1,
+                            location: stmt.location.clone(),
+                            explicit: true
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Pass 2: Hand out the remaining addresses
+    // The address to use next
+    let mut auto_addr = 0;
+
+    for stmt in source.iter_mut() {
+        let symbol = match stmt.value {
+            Statement::Const(ref name, _) if locals.contains_key(name) => continue,
+            Statement::Const(ref name, _) => Some(name.clone()),
+            _ => None
+        };
+        let location = stmt.location.clone();
+
+        for arg in stmt.value.arguments_mut().into_iter() {
+            let size = match auto_size(&arg.value) {
+                Some(size) => size,
+                None => continue
+            };
+
+            if ctx.object && symbol.is_none() {
+                fatal!("auto-filled addresses need a name in object files"; arg)
+            }
+
+            let address = match find_free(&reserved, auto_addr, size) {
+                Some(address) => address,
+                None => fatal!("out of data memory: all {} bytes are in use", MEMORY_SIZE; arg)
+            };
+
+            arg.value = Argument::Address(Some(address as WordSize));
+            allocations.push(Allocation {
+                address: address as WordSize,
+                symbol: symbol.clone(),
+                size: size,
+                location: location.clone(),
+                explicit: false
+            });
+
+            auto_addr = address + size;
+        }
+    }
+
+    // Pass 3: Overlay the local variables of subroutines. A frame is placed
+    // above the frames of the subroutines it's inlined into, all other frames
+    // start at the beginning of the memory used for locals.
+    let mut bases = HashMap::new();
+    for frame in ctx.frames.iter() {
+        frame_base(&frame.routine, &ctx.frames, &frame_sizes, &mut bases);
+    }
+
+    let total = ctx.frames.iter()
+        .map(|frame| {
+            *bases.get(&frame.routine).unwrap() + *frame_sizes.get(&frame.routine).unwrap()
+        })
+        .max()
+        .unwrap_or(0);
+    let start = find_free(&reserved, auto_addr, total);
+
+    for stmt in source.iter_mut() {
+        let location = stmt.location.clone();
+        let (symbol, arg) = match stmt.value {
+            Statement::Const(ref name, ref mut arg) => (name.clone(), arg),
+            _ => continue
+        };
+
+        let offset = match locals.get(&symbol) {
+            Some(&(ref routine, offset)) => *bases.get(routine).unwrap() + offset,
+            None => continue
+        };
+
+        let address = match start {
+            Some(start) => start + offset,
+            None => fatal!("out of data memory: all {} bytes are in use", MEMORY_SIZE; arg)
+        };
+
+        arg.value = Argument::Address(Some(address as WordSize));
+        allocations.push(Allocation {
+            address: address as WordSize,
+            symbol: Some(symbol.clone()),
+            size: *sizes.get(&symbol).unwrap(),
+            location: location,
+            explicit: false
+        });
+    }
+
+    allocations.sort_by(|a, b| a.address.cmp(&b.address));
+    ctx.memory_map = allocations;
+}
+
+
+/// Get the number of bytes an auto-filled address needs
+fn auto_size(arg: &Argument) -> Option<usize> {
+    match *arg {
+        Argument::Address(None) => Some(1),
+        Argument::Array(size) => Some(size as usize),
+        _ => None
+    }
+}
+
+/// Find the first block of `size` unused addresses starting at `from`
+fn find_free(reserved: &[bool], from: usize, size: usize) -> Option<usize> {
+    let mut start = from;
+
+    while start + size <= MEMORY_SIZE {
+        match (start .. start + size).find(|&addr| reserved[addr]) {
+            Some(addr) => start = addr + 1,
+            None => return Some(start)
+        }
+    }
+
+    None
+}
+
+/// Get the offset of a subroutine's frame in the memory used for locals
+fn frame_base(routine: &Ident, frames: &[Frame], frame_sizes: &HashMap<Ident, usize>,
+              bases: &mut HashMap<Ident, usize>) -> usize {
+    if let Some(base) = bases.get(routine) {
+        return *base
+    }
+
+    let frame = frames.iter().find(|frame| frame.routine == *routine).unwrap();
+
+    let mut base = 0;
+    for parent in frame.inlined_into.iter() {
+        let end = frame_base(parent, frames, frame_sizes, bases)
+                  + *frame_sizes.get(parent).unwrap();
+
+        if end > base {
+            base = end;
+        }
+    }
+
+    bases.insert(routine.clone(), base);
+    base
+}
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::{Parser, Context};
+    use machine::{WordSize, MEMORY_SIZE};
+
+    use super::{expand, find_free};
+
+    /// Get the auto-filled addresses of a program
+    fn allocate(source: &str) -> Vec<(String, WordSize)> {
+        let mut program = Parser::new(source, "test.asm").parse();
+        let mut ctx = Context::new();
+        expand(&mut program, &mut ctx);
+
+        ctx.memory_map.iter()
+            .filter(|alloc| !alloc.explicit)
+            .map(|alloc| (alloc.symbol.as_ref().unwrap().to_string(), alloc.address))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_free() {
+        let mut reserved = [false; MEMORY_SIZE];
+        reserved[1] = true;
+        reserved[4] = true;
+
+        assert_eq!(find_free(&reserved, 0, 1), Some(0));
+        assert_eq!(find_free(&reserved, 1, 1), Some(2));
+        assert_eq!(find_free(&reserved, 0, 3), Some(5));
+        assert_eq!(find_free(&reserved, MEMORY_SIZE - 2, 3), None);
+    }
+
+    #[test]
+    fn test_explicit_addresses() {
+        let allocations = allocate("$a = [_]\n\
+                                    $b = [1]\n\
+                                    MOV [2] 0\n\
+                                    MOV [5] 0\n\
+                                    $c = [_]\n\
+                                    $d = [_; 3]\n");
+
+        // Neither `$b` nor `[2]` is handed out again and the array skips `[5]`
+        assert_eq!(allocations, vec![("a".to_string(), 0),
+                                     ("c".to_string(), 3),
+                                     ("d".to_string(), 6)]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_out_of_memory() {
+        allocate("MOV [128] 0\n\
+                  $a = [_; 200]\n");
+    }
+}
@@ -0,0 +1,266 @@
+//! A syntax extension for conditional assembly
+//!
+//! # Example:
+//!
+//! ```
+//! $level = 2
+//!
+//! #ifdef debug
+//!     DPRINT $value
+//! #endif
+//!
+//! #if $level > 1 && !$quiet
+//!     APRINT '!'
+//! #else
+//!     APRINT '.'
+//! #endif
+//! ```
+//!
+//! # Note:
+//!
+//! Conditions are evaluated from top to bottom. They can use constants
+//! defined with `tiny asm -D name=value` and literal constants that are
+//! defined earlier in the same file. Command line definitions take precedence
+//! over the definitions in the source.
+
+use std::collections::HashMap;
+use std::mem;
+use assembler::parser::ast::{Program, Statement, Directive, Argument, Ident};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::Context;
+use assembler::parser::syntax_ext::expressions::evaluate;
+use assembler::util::fatal;
+
+
+/// An `#if` block that hasn't been closed yet
+struct Block {
+    active: bool,       // Whether the current branch is assembled
+    taken: bool,        // Whether any branch has been assembled
+    enclosing: bool,    // Whether the surrounding block is assembled
+    in_else: bool,
+    location: SourceLocation
+}
+
+
+pub fn expand(source: &mut Program, ctx: &mut Context) {
+    // The values of all constants defined so far (`None` if not known yet)
+    let mut consts: HashMap<Ident, Option<i64>> = HashMap::new();
+    for (name, value) in ctx.defines.iter() {
+        consts.insert(name.clone(), Some(*value as i64));
+    }
+
+    let mut blocks: Vec<Block> = vec![];
+
+    for stmt in mem::replace(source, vec![]).into_iter() {
+        let active = blocks.last().map_or(true, |block| block.active);
+
+        let directive = match stmt.value {
+            Statement::Directive(ref directive) => directive.clone(),
+            _ => {
+                if !active {
+                    continue
+                }
+
+                // Remember constant definitions (unless defined from the command line)
+                if let Statement::Const(ref name, ref value) = stmt.value {
+                    if !ctx.defines.contains_key(name) {
+                        let value = match value.value {
+                            Argument::Literal(i) | Argument::Char(i) => Some(i as i64),
+                            _ => None
+                        };
+
+                        consts.insert(name.clone(), value);
+                    }
+                }
+
+                if let Statement::Data(ref name, _, _) = stmt.value {
+                    if !ctx.defines.contains_key(name) {
+                        consts.insert(name.clone(), None);
+                    }
+                }
+
+                source.push(stmt);
+                continue
+            }
+        };
+
+        match directive {
+            Directive::If(ref expr) => {
+                ctx.referenced.extend(expr.value.constants().into_iter().cloned());
+
+                // Conditions in skipped blocks aren't evaluated at all
+                let condition = active && evaluate(expr, &|name: &Ident| {
+                    match consts.get(name) {
+                        Some(&Some(value)) => Some(value),
+                        Some(&None) => {
+                            fatal!("value of ${} is not known at this point", name; expr)
+                        },
+                        None => None
+                    }
+                }) != 0;
+
+                blocks.push(Block {
+                    active: condition,
+                    taken: condition,
+                    enclosing: active,
+                    in_else: false,
+                    location: stmt.location.clone()
+                });
+            },
+
+            Directive::IfDef(ref name) => {
+                ctx.referenced.insert(name.clone());
+
+                let condition = active && consts.contains_key(name);
+
+                blocks.push(Block {
+                    active: condition,
+                    taken: condition,
+                    enclosing: active,
+                    in_else: false,
+                    location: stmt.location.clone()
+                });
+            },
+
+            Directive::Else => {
+                let block = blocks.last_mut().unwrap_or_else(|| {
+                    fatal!("#else without #if"; stmt)
+                });
+
+                if block.in_else {
+                    fatal(format!("duplicate #else for #if in {}", block.location), &stmt.location)
+                }
+
+                block.in_else = true;
+                block.active = block.enclosing && !block.taken;
+                block.taken = true;
+            },
+
+            Directive::EndIf => {
+                if blocks.pop().is_none() {
+                    fatal!("#endif without #if"; stmt)
+                }
+            }
+        }
+    }
+
+    if let Some(block) = blocks.last() {
+        fatal!("unterminated #if"; block)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use assembler::parser::ast::Ident;
+    use assembler::parser::{Parser, Context};
+    use machine::WordSize;
+
+    /// Expand the conditionals with the given command line definitions
+    fn expand_with(source: &str, defines: &[(&str, WordSize)]) -> Vec<String> {
+        let mut program = Parser::new(source, "test.asm").parse();
+        let mut ctx = Context::new();
+        for &(name, value) in defines.iter() {
+            ctx.defines.insert(Ident(name.to_owned()), value);
+        }
+
+        super::expand(&mut program, &mut ctx);
+
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_if() {
+        let source = "$level = 2\n\
+                      #if $level > 1\n\
+                      HALT\n\
+                      #else\n\
+                      DPRINT 1\n\
+                      #endif\n";
+
+        assert_eq!(expand_with(source, &[]), vec!["$level = 2", "HALT"]);
+        assert_eq!(expand_with(source, &[("level", 1)]), vec!["$level = 2", "DPRINT 1"]);
+    }
+
+    #[test]
+    fn test_ifdef() {
+        let source = "#ifdef debug\n\
+                      DPRINT 1\n\
+                      #else\n\
+                      HALT\n\
+                      #endif\n";
+
+        assert_eq!(expand_with(source, &[]), vec!["HALT"]);
+        assert_eq!(expand_with(source, &[("debug", 1)]), vec!["DPRINT 1"]);
+    }
+
+    #[test]
+    fn test_nested() {
+        let source = "#ifdef a\n\
+                      DPRINT 1\n\
+                      #ifdef b\n\
+                      DPRINT 2\n\
+                      #else\n\
+                      DPRINT 3\n\
+                      #endif\n\
+                      #else\n\
+                      #if 1\n\
+                      DPRINT 4\n\
+                      #endif\n\
+                      #endif\n\
+                      HALT\n";
+
+        assert_eq!(expand_with(source, &[]), vec!["DPRINT 4", "HALT"]);
+        assert_eq!(expand_with(source, &[("a", 1)]), vec!["DPRINT 1", "DPRINT 3", "HALT"]);
+        assert_eq!(expand_with(source, &[("a", 1), ("b", 1)]),
+                   vec!["DPRINT 1", "DPRINT 2", "HALT"]);
+    }
+
+    #[test]
+    fn test_skipped_definitions() {
+        // Constants in skipped blocks aren't defined
+        let source = "#if 0\n\
+                      $flag = 1\n\
+                      #endif\n\
+                      #ifdef flag\n\
+                      HALT\n\
+                      #endif\n";
+
+        assert!(expand_with(source, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_define_overrides_source() {
+        let source = "$level = 0\n\
+                      #if $level\n\
+                      HALT\n\
+                      #endif\n";
+
+        assert_eq!(expand_with(source, &[]), vec!["$level = 0"]);
+        assert_eq!(expand_with(source, &[("level", 3)]), vec!["$level = 0", "HALT"]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_unterminated_if() {
+        expand_with("#if 1\n\
+                     HALT\n", &[]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_else_without_if() {
+        expand_with("#else\n\
+                     HALT\n", &[]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_duplicate_else() {
+        expand_with("#if 1\n\
+                     #else\n\
+                     #else\n\
+                     #endif\n", &[]);
+    }
+}
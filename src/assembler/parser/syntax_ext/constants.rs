@@ -1,64 +1,225 @@
-//! A syntax extension for constants
-//!
-//! # Example:
-//!
-//! ```
-//! $const = [0]
-//! MOV $const 2
-//! ```
-//!
-//! Results in:
-//!
-//! ```
-//! MOV [0] 2
-//! ```
-
-use std::collections::HashMap;
-use assembler::parser::ast::{Program, Statement, Argument, Ident};
-
-
-pub fn expand(source: &mut Program) {
-    let mut consts: HashMap<Ident, Argument> = HashMap::new();
-
-    // Pass 1: Collect constant definitions & remove them from the source
-    source.retain(|stmt| {
-        let (name, value) = match stmt.value {
-            Statement::Const(ref name, ref value) => (name, value),
-            _ => return true  // Not a const assignment, keep it
-        };
-
-        // Collect value
-        match value.value {
-            Argument::Literal(_) | Argument::Address(_) => {
-                if consts.insert(name.clone(), value.value.clone()).is_some() {
-                    warn!("redefinition of ${:?}", name; value);
-                }
-            },
-            _ => fatal!("invalid constant value: {:?}", value; value)
-        }
-
-        false  // Remove the definition from the source
-    });
-
-    debug!("Constants: {:?}", consts);
-
-    // Pass 2: Replace usages of constants
-    for stmt in source.iter_mut() {
-        let args = match stmt.value {
-            Statement::Operation(_, ref mut args) => args,
-            _ => continue
-        };
-
-        for arg in args.iter_mut() {
-            // Get the new value if the argument is a constant
-            arg.value = if let Argument::Const(ref name) = arg.value {
-                match consts.get(name) {
-                    Some(value) => value.clone(),
-                    None => fatal!("unknown constant: ${:?}", name; arg)
-                }
-            } else {
-                continue
-            };
-        }
-    }
-}
\ No newline at end of file
+//! A syntax extension for constants
+//!
+//! # Example:
+//!
+//! ```
+//! $const = [0]
+//! MOV $const 2
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! MOV [0] 2
+//! ```
+//!
+//! # Note:
+//!
+//! Constant expressions like `($const * 2)` are evaluated here, too. An
+//! expression that uses exactly one address constant (like `$array + 2`)
+//! results in an address, all others result in a literal. Negative results
+//! (down to `-128`) are stored as two's complement.
+//!
+//! Constants defined on the command line (`tiny asm -D name=value`) replace
+//! the definitions in the source.
+//!
+//! In object files, every byte that holds an auto-filled address is recorded
+//! as a relocation.
+
+use std::collections::{HashMap, HashSet};
+use assembler::parser::ast::{Program, Statement, Argument, Ident};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::{Context, Relocation};
+use assembler::parser::syntax_ext::expressions::evaluate;
+use assembler::util::fatal;
+use machine::WordSize;
+
+
+pub fn expand(source: &mut Program, ctx: &mut Context) {
+    let mut consts: HashMap<Ident, Argument> = HashMap::new();
+    for (name, value) in ctx.defines.iter() {
+        consts.insert(name.clone(), Argument::Literal(*value));
+    }
+
+    // Pass 1: Collect constant definitions & remove them from the source
+    source.retain(|stmt| {
+        let (name, value) = match stmt.value {
+            Statement::Const(ref name, ref value) => (name, value),
+            _ => return true  // Not a const assignment, keep it
+        };
+
+        if ctx.defines.contains_key(name) {
+            return false  // Defined on the command line
+        }
+
+        // Collect value
+        match value.value {
+            Argument::Literal(_) | Argument::Address(_) | Argument::Expr(_) => {
+                if consts.insert(name.clone(), value.value.clone()).is_some() {
+                    warn!("redefinition of ${:?}", name; value);
+                }
+            },
+            _ => fatal!("invalid constant value: {:?}", value; value)
+        }
+
+        false  // Remove the definition from the source
+    });
+
+    debug!("Constants: {:?}", consts);
+
+    // The constants with an auto-filled address
+    let auto: HashSet<&Ident> = ctx.memory_map.iter()
+        .filter(|alloc| !alloc.explicit)
+        .filter_map(|alloc| alloc.symbol.as_ref())
+        .collect();
+    let mut relocations = vec![];
+    let mut offset = 0;
+
+    // Pass 2: Replace usages of constants
+    for stmt in source.iter_mut() {
+        let args = match stmt.value {
+            Statement::Operation(_, ref mut args) => args,
+            _ => continue
+        };
+
+        // The constants each argument uses (before they are replaced)
+        let used: Vec<Vec<Ident>> = args.iter()
+            .map(|arg| arg.value.constants().into_iter().cloned().collect())
+            .collect();
+
+        for arg in args.iter_mut() {
+            // Get the new value if the argument is a constant
+            arg.value = if let Argument::Const(ref name) = arg.value {
+                match consts.get(name) {
+                    Some(value) => value.clone(),
+                    None => fatal!("unknown constant: ${:?}", name; arg)
+                }
+            } else {
+                continue
+            };
+        }
+
+        // Evaluate expressions (including the ones from constant definitions)
+        for arg in args.iter_mut() {
+            let location = arg.location.clone();
+            let value = if let Argument::Expr(ref expr) = arg.value {
+                evaluate(expr, &|name: &Ident| value_of(name, &consts, &location))
+            } else {
+                continue
+            };
+
+            let is_address = match arg.value {
+                Argument::Expr(ref expr) => count_addresses(expr.value.constants(), &consts) == 1,
+                _ => false
+            };
+
+            // Negative literals are stored as two's complement
+            let min = if is_address { 0 } else { -128 };
+            if value < min || value > 255 {
+                fatal!("value out of range: {}", value; arg)
+            }
+
+            arg.value = if is_address {
+                Argument::Address(Some(value as WordSize))
+            } else {
+                Argument::Literal((value & 0xFF) as WordSize)
+            };
+        }
+
+        // Remember the bytes that will contain auto-filled addresses. This is
+        // done after the evaluation which rejects cyclic definitions.
+        if ctx.object {
+            for (i, names) in used.iter().enumerate() {
+                let names: Vec<&Ident> = names.iter().collect();
+                if count_addresses(names.clone(), &consts) != 1 {
+                    continue
+                }
+
+                if let Some(name) = address_constant(names, &consts) {
+                    if auto.contains(&name) {
+                        relocations.push(Relocation::Data(offset + 1 + i, name.clone()));
+                    }
+                }
+            }
+        }
+        offset += 1 + args.len();
+    }
+
+    // Keep the values for later passes
+    ctx.relocations.extend(relocations.into_iter());
+    ctx.constants = consts;
+}
+
+
+/// Get the numeric value of a constant for use in an expression
+pub fn value_of(name: &Ident, consts: &HashMap<Ident, Argument>,
+            location: &SourceLocation) -> Option<i64> {
+    resolve(name, consts, location, &[])
+}
+
+/// Get the value of a constant. `chain` contains the constants whose
+/// definitions lead to this one.
+fn resolve(name: &Ident, consts: &HashMap<Ident, Argument>, location: &SourceLocation,
+           chain: &[Ident]) -> Option<i64> {
+    if chain.contains(name) {
+        let path = chain.iter()
+            .chain(Some(name).into_iter())
+            .map(|name| format!("${}", name))
+            .collect::<Vec<_>>()
+            .connect(" → ");
+
+        fatal(format!("cyclic constant definition: {}", path), location);
+    }
+
+    match consts.get(name) {
+        Some(&Argument::Literal(i)) | Some(&Argument::Char(i))
+            | Some(&Argument::Address(Some(i))) => Some(i as i64),
+        Some(&Argument::Expr(ref expr)) => {
+            let mut chain = chain.to_vec();
+            chain.push(name.clone());
+
+            Some(evaluate(expr, &|name: &Ident| resolve(name, consts, location, &chain)))
+        },
+        Some(_) => fatal(format!("${} can't be used in an expression", name), location),
+        None => None
+    }
+}
+
+/// Count the address constants an expression uses (directly or through other
+/// constants)
+fn count_addresses(names: Vec<&Ident>, consts: &HashMap<Ident, Argument>) -> usize {
+    names.into_iter()
+        .map(|name| match consts.get(name) {
+            Some(&Argument::Address(_)) => 1,
+            Some(&Argument::Expr(ref expr)) => count_addresses(expr.value.constants(), consts),
+            _ => 0
+        })
+        .fold(0, |count, n| count + n)
+}
+
+/// Get the address constant an argument uses (directly or through other
+/// constants)
+fn address_constant<'a>(names: Vec<&'a Ident>,
+                        consts: &'a HashMap<Ident, Argument>) -> Option<&'a Ident> {
+    names.into_iter()
+        .filter_map(|name| match consts.get(name) {
+            Some(&Argument::Address(_)) => Some(name),
+            Some(&Argument::Expr(ref expr)) => address_constant(expr.value.constants(), consts),
+            _ => None
+        })
+        .next()
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::syntax_ext::expand_source;
+
+    #[test]
+    #[should_fail]
+    fn test_cyclic_constants() {
+        expand_source("$a = ($b + 1)\n\
+                       $b = ($a * 2)\n\
+                       MOV [0] $a\n");
+    }
+}
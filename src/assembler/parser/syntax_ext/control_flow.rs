@@ -0,0 +1,446 @@
+//! A syntax extension for structured control flow
+//!
+//! # Example:
+//!
+//! ```
+//! @if($a == $b)
+//!     APRINT '='
+//! @else()
+//!     APRINT '!'
+//! @end()
+//!
+//! @while($i < 10)
+//!     ADD $i 1
+//! @end()
+//!
+//! @for($i, 0, 10)
+//!     DPRINT $i
+//! @end()
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! JNE :if0.else $a $b
+//! APRINT '='
+//! JMP :if0.end
+//! if0.else:
+//! APRINT '!'
+//! if0.end:
+//!
+//! while1.start:
+//! JGE :while1.end $i 10
+//! ADD $i 1
+//! JMP :while1.start
+//! while1.end:
+//!
+//! MOV $i 0
+//! for2.start:
+//! JGE :for2.end $i 10
+//! DPRINT $i
+//! ADD $i 1
+//! JMP :for2.start
+//! for2.end:
+//! ```
+//!
+//! The pseudo instructions (`JNE`, `JGE`, `JLE`) are expanded afterwards.
+//!
+//! # Note:
+//!
+//! A condition is a comparison (`==`, `!=`, `<`, `<=`, `>`, `>=`), optionally
+//! negated with `!`. A plain value is true if it isn't `0`. `@for` counts from
+//! the start value up to (excluding) the end value. The loop variable and the
+//! left side of a comparison have to be addresses (unless the right side is
+//! one).
+
+use std::borrow::ToOwned;
+use std::mem;
+use assembler::parser::ast::{Program, Statement, StatementNode, Argument, MacroArgument,
+                             MacroArgumentNode, Expression, ExpressionNode, Ident,
+                             PseudoMnemonic, Operator};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::operation;
+use assembler::util::fatal;
+
+
+/// A block that hasn't been closed yet
+enum Block {
+    Subroutine,             // `@start`, its `@end` is handled by the subroutine extension
+    If(Ident, bool),        // The label prefix and whether we've seen an `@else`
+    While(Ident),
+    For(Ident, Argument)    // The label prefix and the loop variable
+}
+
+/// A comparison the jump instructions can check
+struct Condition {
+    op: Operator,
+    lhs: Argument,
+    rhs: Argument
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        let op = match self.op {
+            Operator::Eq => Operator::Ne,
+            Operator::Ne => Operator::Eq,
+            Operator::Lt => Operator::Ge,
+            Operator::Ge => Operator::Lt,
+            Operator::Gt => Operator::Le,
+            Operator::Le => Operator::Gt,
+            _ => unreachable!()
+        };
+
+        Condition { op: op, lhs: self.lhs, rhs: self.rhs }
+    }
+}
+
+
+pub fn expand(source: &mut Program) {
+    let mut blocks: Vec<(Block, SourceLocation)> = vec![];
+    let mut next_id = 0;
+
+    for stmt in mem::replace(source, vec![]).into_iter() {
+        let block_macro = match stmt.value {
+            Statement::Macro(ref name, ref args, _) => Some((name.clone(), args.clone())),
+            _ => None
+        };
+
+        let (name, args) = match block_macro {
+            Some(block_macro) => block_macro,
+            None => {
+                source.push(stmt);
+                continue
+            }
+        };
+        let location = stmt.location.clone();
+
+        match name.as_str() {
+            "start" => {
+                blocks.push((Block::Subroutine, location));
+                source.push(stmt);
+            },
+            "if" => {
+                check_argc(&stmt, &args, 1);
+
+                let prefix = Ident(format!("if{}", next_id));
+                next_id += 1;
+
+                // Skip to the else branch if the condition doesn't hold
+                let cond = condition(&args[0]).negate();
+                source.push(jump_if(&cond, &prefix, "else", &location));
+
+                blocks.push((Block::If(prefix, false), location));
+            },
+            "else" => {
+                check_argc(&stmt, &args, 0);
+
+                match blocks.pop() {
+                    Some((Block::If(prefix, false), start)) => {
+                        source.push(jump(&prefix, "end", &location));
+                        source.push(label(&prefix, "else", &location));
+
+                        blocks.push((Block::If(prefix, true), start));
+                    },
+                    Some((Block::If(_, true), _)) => fatal!("duplicate @else"; stmt),
+                    _ => fatal!("@else without @if"; stmt)
+                }
+            },
+            "while" => {
+                check_argc(&stmt, &args, 1);
+
+                let prefix = Ident(format!("while{}", next_id));
+                next_id += 1;
+
+                source.push(label(&prefix, "start", &location));
+
+                let cond = condition(&args[0]).negate();
+                source.push(jump_if(&cond, &prefix, "end", &location));
+
+                blocks.push((Block::While(prefix), location));
+            },
+            "for" => {
+                check_argc(&stmt, &args, 3);
+
+                let prefix = Ident(format!("for{}", next_id));
+                next_id += 1;
+
+                let var = argument(&args[0]);
+                let from = argument(&args[1]);
+                let to = argument(&args[2]);
+
+                source.push(operation("MOV", vec![var.clone(), from], &location));
+                source.push(label(&prefix, "start", &location));
+
+                let cond = Condition { op: Operator::Ge, lhs: var.clone(), rhs: to };
+                source.push(jump_if(&cond, &prefix, "end", &location));
+
+                blocks.push((Block::For(prefix, var), location));
+            },
+            "end" => {
+                match blocks.pop() {
+                    Some((Block::Subroutine, _)) => source.push(stmt),
+                    Some((Block::If(prefix, has_else), _)) => {
+                        if !has_else {
+                            source.push(label(&prefix, "else", &location));
+                        }
+                        source.push(label(&prefix, "end", &location));
+                    },
+                    Some((Block::While(prefix), _)) => {
+                        source.push(jump(&prefix, "start", &location));
+                        source.push(label(&prefix, "end", &location));
+                    },
+                    Some((Block::For(prefix, var), _)) => {
+                        source.push(operation("ADD", vec![var, Argument::Literal(1)], &location));
+                        source.push(jump(&prefix, "start", &location));
+                        source.push(label(&prefix, "end", &location));
+                    },
+                    None => fatal!("@end without a block to close"; stmt)
+                }
+            },
+            _ => source.push(stmt)
+        }
+    }
+
+    for (block, location) in blocks.into_iter() {
+        match block {
+            Block::Subroutine => {},  // Reported by the subroutine extension
+            _ => fatal("unterminated block".to_owned(), &location)
+        }
+    }
+}
+
+
+fn check_argc(stmt: &StatementNode, args: &[MacroArgumentNode], argc: usize) {
+    if args.len() != argc {
+        fatal!("expected {} args, found {}", argc, args.len(); stmt)
+    }
+}
+
+fn argument(marg: &MacroArgumentNode) -> Argument {
+    match marg.value {
+        MacroArgument::Argument(ref arg) => arg.value.clone(),
+        ref other => fatal!("expected argument, found `{}`", other; marg)
+    }
+}
+
+/// Get the condition a macro argument describes
+fn condition(marg: &MacroArgumentNode) -> Condition {
+    match marg.value {
+        MacroArgument::Argument(ref arg) => {
+            let expr = Expression::new(Expression::Value(arg.value.clone()),
+                                       arg.location.clone());
+            condition_of(&expr)
+        },
+        ref other => fatal!("expected condition, found `{}`", other; marg)
+    }
+}
+
+fn condition_of(expr: &ExpressionNode) -> Condition {
+    match expr.value {
+        Expression::Value(Argument::Expr(ref inner)) => condition_of(inner),
+        Expression::Unary(Operator::Not, ref inner) => condition_of(inner).negate(),
+        Expression::Binary(Operator::And, _, _) | Expression::Binary(Operator::Or, _, _) => {
+            fatal!("`&&` and `||` can't be used in conditions"; expr)
+        },
+        Expression::Binary(op, ref lhs, ref rhs) if is_comparison(op) => {
+            Condition { op: op, lhs: operand(lhs), rhs: operand(rhs) }
+        },
+        _ => Condition { op: Operator::Ne, lhs: operand(expr), rhs: Argument::Literal(0) }
+    }
+}
+
+fn is_comparison(op: Operator) -> bool {
+    match op {
+        Operator::Eq | Operator::Ne | Operator::Lt
+            | Operator::Le | Operator::Gt | Operator::Ge => true,
+        _ => false
+    }
+}
+
+/// Convert one side of a comparison back to an argument
+fn operand(expr: &ExpressionNode) -> Argument {
+    match expr.value {
+        Expression::Value(ref arg) => arg.clone(),
+        _ => Argument::Expr(Box::new(expr.clone()))
+    }
+}
+
+/// Build the jump to `prefix.target` that is taken if the condition holds
+fn jump_if(cond: &Condition, prefix: &Ident, target: &str,
+           location: &SourceLocation) -> StatementNode {
+    // The first operand of a comparison can't be a literal
+    let (op, lhs, rhs) = match cond.lhs {
+        Argument::Literal(_) | Argument::Char(_) => {
+            let op = match cond.op {
+                Operator::Lt => Operator::Gt,
+                Operator::Gt => Operator::Lt,
+                Operator::Le => Operator::Ge,
+                Operator::Ge => Operator::Le,
+                op => op
+            };
+
+            (op, cond.rhs.clone(), cond.lhs.clone())
+        },
+        _ => (cond.op, cond.lhs.clone(), cond.rhs.clone())
+    };
+
+    let args = vec![label_argument(prefix, target), lhs, rhs];
+
+    match op {
+        Operator::Eq => operation("JEQ", args, location),
+        Operator::Lt => operation("JLS", args, location),
+        Operator::Gt => operation("JGT", args, location),
+        Operator::Ne => pseudo_operation(PseudoMnemonic::JNE, args, location),
+        Operator::Le => pseudo_operation(PseudoMnemonic::JLE, args, location),
+        Operator::Ge => pseudo_operation(PseudoMnemonic::JGE, args, location),
+        _ => unreachable!()
+    }
+}
+
+fn label_name(prefix: &Ident, name: &str) -> Ident {
+    Ident(format!("{}.{}", prefix, name))
+}
+
+fn label_argument(prefix: &Ident, name: &str) -> Argument {
+    Argument::Label(label_name(prefix, name))
+}
+
+fn label(prefix: &Ident, name: &str, location: &SourceLocation) -> StatementNode {
+    Statement::new(Statement::Label(label_name(prefix, name)), location.clone())
+}
+
+fn jump(prefix: &Ident, name: &str, location: &SourceLocation) -> StatementNode {
+    operation("JMP", vec![label_argument(prefix, name)], location)
+}
+
+/// Build a pseudo operation statement (expanded by the pseudo instruction extension)
+fn pseudo_operation(mnem: PseudoMnemonic, args: Vec<Argument>,
+                    location: &SourceLocation) -> StatementNode {
+    let args = args.into_iter()
+        .map(|arg| Argument::new(arg, location.clone()))
+        .collect();
+
+    Statement::new(Statement::PseudoOp(mnem, args), location.clone())
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::codegen::generate_binary;
+    use assembler::parser::Parser;
+    use assembler::parser::syntax_ext::expand_source;
+    use vm;
+
+    /// Expand the control flow macros (pseudo instructions are left as they are)
+    fn expand_code(source: &str) -> Vec<String> {
+        let mut program = Parser::new(source, "test.asm").parse();
+        super::expand(&mut program);
+
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_if_else() {
+        assert_eq!(expand_code("@if($a == $b)\n\
+                                DPRINT 1\n\
+                                @else()\n\
+                                DPRINT 2\n\
+                                @end()\n"),
+                   vec!["JNE :if0.else $a $b", "DPRINT 1", "JMP :if0.end", "if0.else:",
+                        "DPRINT 2", "if0.end:"]);
+    }
+
+    #[test]
+    fn test_if() {
+        assert_eq!(expand_code("@if($a < 3)\n\
+                                DPRINT 1\n\
+                                @end()\n"),
+                   vec!["JGE :if0.else $a 3", "DPRINT 1", "if0.else:", "if0.end:"]);
+
+        // The literal is moved to the right side
+        assert_eq!(expand_code("@if(3 > $a)\n\
+                                DPRINT 1\n\
+                                @end()\n"),
+                   vec!["JGE :if0.else $a 3", "DPRINT 1", "if0.else:", "if0.end:"]);
+    }
+
+    #[test]
+    fn test_while() {
+        assert_eq!(expand_code("@while($i)\n\
+                                SUB $i 1\n\
+                                @end()\n"),
+                   vec!["while0.start:", "JEQ :while0.end $i 0", "SUB $i 1",
+                        "JMP :while0.start", "while0.end:"]);
+    }
+
+    #[test]
+    fn test_for() {
+        assert_eq!(expand_code("@for($i, 0, 10)\n\
+                                DPRINT $i\n\
+                                @end()\n"),
+                   vec!["MOV $i 0", "for0.start:", "JGE :for0.end $i 10", "DPRINT $i",
+                        "ADD $i 1", "JMP :for0.start", "for0.end:"]);
+    }
+
+    #[test]
+    fn test_nested() {
+        assert_eq!(expand_code("@for($i, 0, 2)\n\
+                                @if(!($i != 1))\n\
+                                DPRINT $i\n\
+                                @end()\n\
+                                @end()\n"),
+                   vec!["MOV $i 0", "for0.start:", "JGE :for0.end $i 2",
+                        "JNE :if1.else $i 1", "DPRINT $i", "if1.else:", "if1.end:",
+                        "ADD $i 1", "JMP :for0.start", "for0.end:"]);
+    }
+
+    #[test]
+    fn test_vm() {
+        let (program, _) = expand_source("$sum = [0]\n\
+                                          $i = [1]\n\
+                                          $skipped = [2]\n\
+                                          @for($i, 0, 5)\n\
+                                          @if($i == 3)\n\
+                                          ADD $skipped 1\n\
+                                          @else()\n\
+                                          ADD $sum $i\n\
+                                          @end()\n\
+                                          @end()\n\
+                                          @while($sum < 20)\n\
+                                          ADD $sum 10\n\
+                                          @end()\n\
+                                          HALT\n");
+
+        let code: Vec<u8> = generate_binary(program).into_iter()
+            .flat_map(|stmt| stmt.into_iter())
+            .collect();
+        let memory = vm::run(&code, 0);
+
+        // 0 + 1 + 2 + 4, then 10 twice
+        assert_eq!(memory[0], 27);
+        assert_eq!(memory[1], 5);
+        assert_eq!(memory[2], 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_else_without_if() {
+        expand_code("@while($i)\n\
+                     @else()\n\
+                     @end()\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_unterminated_block() {
+        expand_code("@if($i)\n\
+                     HALT\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_logical_operators() {
+        expand_code("@if($a == 1 && $b == 2)\n\
+                     @end()\n");
+    }
+}
@@ -0,0 +1,166 @@
+//! A syntax extension for initialized data
+//!
+//! # Example:
+//!
+//! ```
+//! $table = [_] = (1, 4, 9)
+//! $buffer = [_; 8] = ('a', 'b')
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! MOV $table 1
+//! MOV ($table + 1) 4
+//! MOV ($table + 2) 9
+//! MOV $buffer 'a'
+//! MOV ($buffer + 1) 'b'
+//!
+//! $table = [_; 3]
+//! $buffer = [_; 8]
+//! ```
+//!
+//! # Note:
+//!
+//! The `MOV`s are placed at the very beginning of the program. As the data
+//! memory starts zeroed, values of `0` are skipped.
+
+use assembler::parser::ast::{Program, Statement, Argument, Expression, Mnemonic, Operator};
+use machine::WordSize;
+
+
+pub fn expand(source: &mut Program) {
+    let mut prologue = vec![];
+    let mut in_subroutine = false;
+
+    for stmt in source.iter_mut() {
+        let (name, value, values) = match stmt.value {
+            Statement::Macro(ref ident, _, _) => {
+                match ident.as_str() {
+                    "start" => in_subroutine = true,
+                    "end" => in_subroutine = false,
+                    _ => {}
+                }
+
+                continue
+            },
+            Statement::Data(ref name, ref value, ref values) => {
+                (name.clone(), value.clone(), values.clone())
+            },
+            _ => continue
+        };
+
+        if in_subroutine {
+            fatal!("initialized data can't be declared inside of a subroutine"; stmt)
+        }
+
+        // Reserve as many bytes as there are values
+        let address = match value.value {
+            Argument::Address(None) if values.len() > 1 => {
+                Argument::Array(values.len() as WordSize)
+            },
+            Argument::Array(size) if values.len() > size as usize => {
+                fatal!("too many values: found {}, array size is {}", values.len(), size; stmt)
+            },
+            Argument::Address(Some(_)) if values.len() > 1 => {
+                fatal!("explicit addresses can only be initialized with one value"; stmt)
+            },
+            Argument::Address(_) | Argument::Array(_) => value.value.clone(),
+            _ => fatal!("expected an address, found {}", value; value)
+        };
+
+        // Initialize the memory
+        for (i, init) in values.into_iter().enumerate() {
+            match init.value {
+                Argument::Literal(0) | Argument::Char(0) => continue,
+                _ => {}
+            }
+
+            let target = if i == 0 {
+                Argument::Const(name.clone())
+            } else {
+                let constant = Expression::new(Expression::Value(Argument::Const(name.clone())),
+                                               init.location.clone());
+                let offset = Expression::new(Expression::Value(Argument::Literal(i as WordSize)),
+                                             init.location.clone());
+                Argument::Expr(Box::new(Expression::new(
+                    Expression::Binary(Operator::Add, Box::new(constant), Box::new(offset)),
+                    init.location.clone()
+                )))
+            };
+
+            prologue.push(Statement::new(
+                Statement::Operation(Mnemonic("MOV".parse().unwrap()),
+                                     vec![Argument::new(target, init.location.clone()), init]),
+                stmt.location.clone()
+            ));
+        }
+
+        stmt.value = Statement::Const(name, Argument::new(address, value.location.clone()));
+    }
+
+    for (i, stmt) in prologue.into_iter().enumerate() {
+        source.insert(i, stmt);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::Parser;
+
+    fn expand(source: &str) -> Vec<String> {
+        let mut program = Parser::new(source, "test.asm").parse();
+        super::expand(&mut program);
+
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_array() {
+        assert_eq!(expand("HALT\n\
+                           $table = [_] = (1, 4, 9)\n"),
+                   vec!["MOV $table 1", "MOV ($table + 1) 4", "MOV ($table + 2) 9",
+                        "HALT", "$table = [_; 3]"]);
+    }
+
+    #[test]
+    fn test_sized_array() {
+        assert_eq!(expand("$buffer = [_; 8] = (5, 6)\n"),
+                   vec!["MOV $buffer 5", "MOV ($buffer + 1) 6", "$buffer = [_; 8]"]);
+    }
+
+    #[test]
+    fn test_single_value() {
+        assert_eq!(expand("$a = [_] = (3)\n\
+                           $b = [7] = (2)\n\
+                           HALT\n"),
+                   vec!["MOV $a 3", "MOV $b 2", "$a = [_]", "$b = [7]", "HALT"]);
+    }
+
+    #[test]
+    fn test_skip_zero() {
+        assert_eq!(expand("$table = [_] = (0, 1, 0)\n"),
+                   vec!["MOV ($table + 1) 1", "$table = [_; 3]"]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_too_many_values() {
+        expand("$table = [_; 2] = (1, 2, 3)\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_empty_array() {
+        expand("$table = [_; 0] = (1)\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_in_subroutine() {
+        expand("@start(f, 0)\n\
+                $table = [_] = (1, 2)\n\
+                @end()\n");
+    }
+}
@@ -0,0 +1,185 @@
+//! Evaluation of constant expressions
+//!
+//! All values are integers. Comparisons and logical operators evaluate to
+//! `1` (true) or `0` (false), any non-zero value counts as true.
+
+use assembler::parser::ast::{Expression, ExpressionNode, Argument, Operator, Ident};
+
+
+/// Evaluate an expression. Constants are resolved using `lookup`.
+pub fn evaluate<F>(expr: &ExpressionNode, lookup: &F) -> i64
+        where F: Fn(&Ident) -> Option<i64> {
+    evaluate_with_labels(expr, lookup, &|_: &Ident| None)
+}
+
+/// Evaluate an expression that can use labels, too. Labels are resolved
+/// using `labels`.
+pub fn evaluate_with_labels<F, G>(expr: &ExpressionNode, lookup: &F, labels: &G) -> i64
+        where F: Fn(&Ident) -> Option<i64>, G: Fn(&Ident) -> Option<i64> {
+    match expr.value {
+        Expression::Value(ref value) => {
+            match *value {
+                Argument::Literal(i) | Argument::Char(i) => i as i64,
+                Argument::Const(ref name) => match lookup(name) {
+                    Some(value) => value,
+                    None => fatal!("unknown constant: ${}", name; expr)
+                },
+                Argument::Label(ref name) => match labels(name) {
+                    Some(value) => value,
+                    None => fatal!("label :{} can't be used here", name; expr)
+                },
+                Argument::Expr(ref inner) => evaluate_with_labels(inner, lookup, labels),
+                ref value => fatal!("invalid value in expression: {}", value; expr)
+            }
+        },
+
+        Expression::Unary(op, ref operand) => {
+            let value = evaluate_with_labels(operand, lookup, labels);
+
+            match op {
+                Operator::Not => (value == 0) as i64,
+                Operator::Sub => match 0i64.checked_sub(value) {
+                    Some(value) => value,
+                    None => fatal!("arithmetic overflow"; expr)
+                },
+                _ => unreachable!()
+            }
+        },
+
+        Expression::Binary(op, ref lhs, ref rhs) => {
+            let lhs = evaluate_with_labels(lhs, lookup, labels);
+
+            // Don't evaluate the right hand side if the result is already known
+            match op {
+                Operator::And if lhs == 0 => return 0,
+                Operator::Or if lhs != 0 => return 1,
+                _ => {}
+            }
+
+            let rhs = evaluate_with_labels(rhs, lookup, labels);
+
+            match op {
+                Operator::Add | Operator::Sub | Operator::Mul => {
+                    let result = match op {
+                        Operator::Add => lhs.checked_add(rhs),
+                        Operator::Sub => lhs.checked_sub(rhs),
+                        _ => lhs.checked_mul(rhs)
+                    };
+
+                    match result {
+                        Some(value) => value,
+                        None => fatal!("arithmetic overflow"; expr)
+                    }
+                },
+                Operator::Div => {
+                    if rhs == 0 {
+                        fatal!("division by zero"; expr)
+                    }
+
+                    lhs / rhs
+                },
+                Operator::Eq  => (lhs == rhs) as i64,
+                Operator::Ne  => (lhs != rhs) as i64,
+                Operator::Lt  => (lhs < rhs) as i64,
+                Operator::Le  => (lhs <= rhs) as i64,
+                Operator::Gt  => (lhs > rhs) as i64,
+                Operator::Ge  => (lhs >= rhs) as i64,
+                Operator::Shl | Operator::Shr => {
+                    if rhs < 0 || rhs > 63 {
+                        fatal!("invalid shift amount: {}", rhs; expr)
+                    }
+
+                    if op == Operator::Shl { lhs << rhs as usize } else { lhs >> rhs as usize }
+                },
+                Operator::And | Operator::Or => (rhs != 0) as i64,
+                Operator::Not => unreachable!()
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::Parser;
+    use assembler::parser::ast::Ident;
+    use super::evaluate;
+
+    /// Evaluate an expression where `$x` is 10 and all other constants are unknown
+    fn eval(source: &str) -> i64 {
+        let expr = Parser::new(source, "test.asm").parse_expression();
+
+        evaluate(&expr, &|name: &Ident| {
+            if name.as_str() == "x" { Some(10) } else { None }
+        })
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval("$x + 2"), 12);
+        assert_eq!(eval("$x - 12"), -2);
+        assert_eq!(eval("$x / 3"), 3);
+        assert_eq!(eval("-$x"), -10);
+        assert_eq!(eval("'a' + 1"), 98);
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("1 + 1 << 2"), 8);
+        assert_eq!(eval("1 << 2 == 4"), 1);
+        assert_eq!(eval("-2 * 3"), -6);
+        assert_eq!(eval("!0 + 1"), 2);
+        assert_eq!(eval("1 == 1 && 2 < 1 || 3 > 2"), 1);
+        assert_eq!(eval("0 || 1 && 0"), 0);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(eval("$x == 10"), 1);
+        assert_eq!(eval("$x != 10"), 0);
+        assert_eq!(eval("$x <= 10"), 1);
+        assert_eq!(eval("$x >= 11"), 0);
+        assert_eq!(eval("$x >> 1"), 5);
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        // `$unknown` would be an error if it was evaluated
+        assert_eq!(eval("0 && $unknown"), 0);
+        assert_eq!(eval("1 || $unknown"), 1);
+        assert_eq!(eval("2 && 3"), 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_unknown_constant() {
+        eval("1 && $unknown");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_overflow() {
+        eval("(1 << 62) * 4");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_division_by_zero() {
+        eval("$x / ($x - 10)");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_shift_amount() {
+        eval("1 << 64");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_negative_shift() {
+        eval("1 >> (0 - 1)");
+    }
+}
@@ -0,0 +1,392 @@
+//! A control-flow graph of the program
+//!
+//! # Example:
+//!
+//! ```
+//! MOV $x 1
+//! JEQ :end $x 1
+//! JMP :end
+//! DPRINT $x       ; Unreachable
+//! end:
+//! HALT
+//! ```
+//!
+//! The program is split into basic blocks at labels and after jumps. The
+//! edges lead to the jump targets and, unless the block ends with `JMP` or
+//! `HALT`, to the next block:
+//!
+//! ```
+//! b0: MOV $x 1, JEQ :end $x 1     -> b1, b3
+//! b1: JMP :end                    -> b3
+//! b2: DPRINT $x                   -> b3
+//! b3: end: HALT
+//! ```
+//!
+//! Blocks that can't be reached from the start of the program (`b2`) result
+//! in a warning. With `-O` they are removed and the peephole optimizer runs
+//! again, until neither finds anything to change.
+//!
+//! # Note:
+//!
+//! Indirect jumps (like `JMP $jump_back`) can reach all labels that are used
+//! as a value (like `MOV $jump_back :ret0`). Code addresses that aren't
+//! written as labels aren't known.
+//!
+//! In object files, all labels are reachable as other objects may jump there.
+
+use std::mem;
+use std::collections::{HashMap, HashSet};
+use assembler::parser::ast::{Program, Statement, StatementNode, Argument, Ident, Mnemonic};
+use assembler::parser::syntax_ext::{Context, peephole};
+use assembler::parser::syntax_ext::peephole::{Optimization, is_jump, jumps_to_number,
+                                              remove_relocations};
+use assembler::parser::syntax_ext::subroutines::routine_at;
+use machine::Mnemonic as Instruction;
+
+
+/// A sequence of instructions that is always executed from start to end
+pub struct BasicBlock {
+    pub start: usize,               // The index of the first statement
+    pub end: usize,                 // The index after the last statement
+    pub offset: usize,              // The address of the first instruction
+    pub size: usize,                // Bytes
+    pub labels: Vec<Ident>,
+    pub instructions: Vec<String>,
+    pub successors: Vec<usize>,
+    pub reachable: bool,
+    exit: Option<(Instruction, Option<Argument>)>  // The last instruction
+}
+
+impl BasicBlock {
+    fn new(start: usize, offset: usize) -> BasicBlock {
+        BasicBlock {
+            start: start,
+            end: start,
+            offset: offset,
+            size: 0,
+            labels: vec![],
+            instructions: vec![],
+            successors: vec![],
+            reachable: false,
+            exit: None
+        }
+    }
+}
+
+
+pub struct FlowGraph {
+    pub blocks: Vec<BasicBlock>
+}
+
+impl FlowGraph {
+    pub fn new() -> FlowGraph {
+        FlowGraph {
+            blocks: vec![]
+        }
+    }
+
+    /// Build the graph for a program. In object files, all labels are entry points.
+    pub fn build(source: &Program, object: bool) -> FlowGraph {
+        let mut blocks = vec![];
+        let mut current = BasicBlock::new(0, 0);
+        let mut offset = 0;
+        let mut address_taken = HashSet::new();
+
+        // Split the program into blocks
+        for (i, stmt) in source.iter().enumerate() {
+            if let Statement::Label(ref name) = stmt.value {
+                if current.instructions.len() > 0 {
+                    blocks.push(mem::replace(&mut current, BasicBlock::new(i, offset)));
+                }
+                current.labels.push(name.clone());
+            }
+
+            current.end = i + 1;
+
+            if let Statement::Operation(Mnemonic(instr), ref args) = stmt.value {
+                // Labels used as a value may be the target of an indirect jump
+                for (j, arg) in args.iter().enumerate() {
+                    if let Argument::Label(ref name) = arg.value {
+                        if !(j == 0 && is_jump(instr)) {
+                            address_taken.insert(name.clone());
+                        }
+                    }
+                }
+
+                current.instructions.push(stmt.to_string());
+                current.size += stmt.value.size();
+                current.exit = Some((instr, args.get(0).map(|arg| arg.value.clone())));
+                offset += stmt.value.size();
+
+                if is_jump(instr) || instr == Instruction::HALT {
+                    blocks.push(mem::replace(&mut current, BasicBlock::new(i + 1, offset)));
+                }
+            }
+        }
+
+        if current.end > current.start {
+            blocks.push(current);
+        }
+
+        let mut graph = FlowGraph { blocks: blocks };
+        let indirect = graph.connect(&address_taken);
+        graph.mark_reachable(&indirect, object);
+
+        graph
+    }
+
+    /// Add the edges between the blocks. Returns the possible targets of
+    /// indirect jumps.
+    fn connect(&mut self, address_taken: &HashSet<Ident>) -> Vec<usize> {
+        let mut labels = HashMap::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            for name in block.labels.iter() {
+                labels.insert(name.clone(), i);
+            }
+        }
+
+        let indirect: Vec<usize> = address_taken.iter()
+            .filter_map(|name| labels.get(name).cloned())
+            .collect();
+
+        for i in 0 .. self.blocks.len() {
+            let next = if i + 1 < self.blocks.len() { vec![i + 1] } else { vec![] };
+
+            let mut successors = match self.blocks[i].exit {
+                Some((Instruction::HALT, _)) => vec![],
+                Some((instr, Some(ref target))) if is_jump(instr) => {
+                    let mut targets: Vec<usize> = match *target {
+                        Argument::Label(ref name) => {
+                            labels.get(name).cloned().into_iter().collect()
+                        },
+                        Argument::Literal(address) | Argument::Char(address) => {
+                            self.block_at(address as usize).into_iter().collect()
+                        },
+                        _ => indirect.clone()
+                    };
+
+                    if instr != Instruction::JMP {
+                        targets.extend(next.into_iter());
+                    }
+
+                    targets
+                },
+                _ => next
+            };
+
+            successors.sort();
+            successors.dedup();
+            self.blocks[i].successors = successors;
+        }
+
+        indirect
+    }
+
+    /// Find the blocks that can be reached from the start of the program. The
+    /// targets of indirect jumps are always kept, as the code storing their
+    /// address may still use them.
+    fn mark_reachable(&mut self, indirect: &[usize], object: bool) {
+        let mut pending: Vec<usize> = (0 .. self.blocks.len())
+            .filter(|&i| i == 0 || (object && self.blocks[i].labels.len() > 0))
+            .chain(indirect.iter().cloned())
+            .collect();
+
+        while let Some(i) = pending.pop() {
+            if self.blocks[i].reachable {
+                continue
+            }
+
+            self.blocks[i].reachable = true;
+            pending.extend(self.blocks[i].successors.iter().cloned());
+        }
+    }
+
+    /// Find the block containing a program address
+    fn block_at(&self, address: usize) -> Option<usize> {
+        self.blocks.iter()
+            .position(|block| block.offset <= address && address < block.offset + block.size)
+    }
+
+    /// Format the graph for Graphviz (unreachable blocks are dashed)
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph program {\n    node [shape=box, fontname=monospace];\n\n");
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut text = format!("{:#04x}\\l", block.offset);
+            for name in block.labels.iter() {
+                text.push_str(&format!("{}:\\l", name));
+            }
+            for instruction in block.instructions.iter() {
+                text.push_str(&format!("    {}\\l", instruction.replace("\"", "\\\"")));
+            }
+
+            let style = if block.reachable { "" } else { ", style=dashed" };
+            dot.push_str(&format!("    b{} [label=\"{}\"{}];\n", i, text, style));
+        }
+
+        dot.push_str("\n");
+        for (i, block) in self.blocks.iter().enumerate() {
+            for successor in block.successors.iter() {
+                dot.push_str(&format!("    b{} -> b{};\n", i, successor));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+
+pub fn check(source: &mut Program, ctx: &mut Context) {
+    let mut graph = FlowGraph::build(source, ctx.object);
+
+    for block in graph.blocks.iter().filter(|block| !block.reachable) {
+        // Only report code that was written by hand
+        let written = source[block.start .. block.end].iter().find(|stmt| {
+            is_operation(stmt) && &**stmt.location.filename != "<internal>"
+        });
+
+        if let Some(stmt) = written {
+            warn!("unreachable code: {}", stmt; stmt);
+        }
+    }
+
+    // Removing code would move the targets of numeric jumps. Removing blocks
+    // can enable further optimizations (and vice versa).
+    if ctx.optimize && !source.iter().any(|stmt| jumps_to_number(&stmt.value)) {
+        while remove_unreachable(source, &graph, ctx) {
+            peephole::optimize(source, ctx);
+            graph = FlowGraph::build(source, ctx.object);
+        }
+    }
+
+    // Keep the graph for `--cfg`
+    ctx.flow_graph = graph;
+}
+
+/// Remove the unreachable blocks. Returns whether there were any.
+fn remove_unreachable(source: &mut Program, graph: &FlowGraph, ctx: &mut Context) -> bool {
+    // The subroutine each statement belongs to
+    let routines: Vec<Option<usize>> = (0 .. source.len())
+        .map(|i| routine_at(source, &ctx.emitted, i))
+        .collect();
+
+    let mut program = Vec::with_capacity(source.len());
+    let mut offset = 0;
+    let mut changed = false;
+
+    for (block, stmts) in graph.blocks.iter().zip(split_blocks(source, graph).into_iter()) {
+        if block.reachable || block.size == 0 {
+            offset += block.size;
+            program.extend(stmts.into_iter());
+            continue
+        }
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            if let Some(routine) = routines[block.start + i] {
+                ctx.emitted[routine].size -= stmt.value.size();
+            }
+        }
+
+        remove_relocations(&mut ctx.relocations, offset, block.size);
+        ctx.optimized.push(Optimization {
+            location: stmts.iter()
+                .find(|stmt| is_operation(stmt))
+                .map_or(stmts[0].location.clone(), |stmt| stmt.location.clone()),
+            description: format!("removed {} unreachable instructions", block.instructions.len()),
+            saved: block.size
+        });
+
+        // Keep the labels and the assertions
+        program.extend(stmts.into_iter().filter(|stmt| !is_operation(stmt)));
+        changed = true;
+    }
+
+    *source = program;
+    changed
+}
+
+fn is_operation(stmt: &StatementNode) -> bool {
+    match stmt.value {
+        Statement::Operation(..) => true,
+        _ => false
+    }
+}
+
+/// Split the statements of the program into the blocks of the graph
+fn split_blocks(source: &mut Program, graph: &FlowGraph) -> Vec<Program> {
+    let mut stmts = mem::replace(source, vec![]).into_iter();
+
+    graph.blocks.iter()
+        .map(|block| stmts.by_ref().take(block.end - block.start).collect())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::codegen::generate_binary;
+    use assembler::parser::{Parser, Context, expand_syntax_extensions};
+
+    fn expand(source: &str, optimize: bool) -> (Context, Vec<Vec<u8>>) {
+        let mut program = Parser::new(source, "test.asm").parse();
+        let mut ctx = Context::new();
+        ctx.optimize = optimize;
+
+        expand_syntax_extensions(&mut program, &mut ctx);
+        let binary = generate_binary(program);
+
+        (ctx, binary)
+    }
+
+    const SOURCE: &'static str = "MOV [0] 1\n\
+                                  JEQ :end [0] 1\n\
+                                  JMP :end\n\
+                                  DPRINT [0]\n\
+                                  end:\n\
+                                  HALT\n\
+                                  APRINT 'x'\n";
+
+    #[test]
+    fn test_graph() {
+        let (ctx, _) = expand(SOURCE, false);
+        let blocks = &ctx.flow_graph.blocks;
+
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks.iter().map(|b| b.successors.clone()).collect::<Vec<_>>(),
+                   vec![vec![1, 3], vec![3], vec![3], vec![], vec![]]);
+        assert_eq!(blocks.iter().map(|b| b.reachable).collect::<Vec<_>>(),
+                   vec![true, true, false, true, false]);
+        assert_eq!(blocks[3].offset, 11);
+
+        assert!(ctx.flow_graph.to_dot().contains("    b0 -> b3;\n"));
+    }
+
+    #[test]
+    fn test_remove() {
+        let (ctx, binary) = expand(SOURCE, true);
+
+        // Without the `DPRINT`, the `JMP :end` jumps to the next instruction
+        assert_eq!(binary, vec![vec![0x08, 0x00, 0x01],
+                                vec![0x17, 0x07, 0x00, 0x01],
+                                vec![0xff]]);
+        assert!(ctx.flow_graph.blocks.iter().all(|block| block.reachable));
+    }
+
+    #[test]
+    fn test_routine_size() {
+        let (ctx, _) = expand("@call(f)\n\
+                               HALT\n\
+                               @start(f, 0)\n\
+                               MOV $return 1\n\
+                               JMP $jump_back\n\
+                               DPRINT $return\n\
+                               @end()\n", true);
+
+        // The `DPRINT` and the generated return jump after it are unreachable
+        assert_eq!(ctx.emitted[0].size, 5);
+        assert_eq!(ctx.size.subroutines, vec![("(main program)".to_string(), 6),
+                                              ("f".to_string(), 5)]);
+    }
+}
@@ -1,72 +1,144 @@
-//! A syntax extension that replaces labels with the referenced instruction number
-//!
-//! # Example:
-//!
-//! ```
-//! label:
-//! GOTO :label
-//! ```
-//!
-//! Results in:
-//!
-//! ```
-//! GOTO 0
-//! ```
-
-use std::collections::HashMap;
-use assembler::parser::ast::{Program, Statement, Argument, Ident};
-
-
-pub fn expand(source: &mut Program) {
-    let mut labels: HashMap<Ident, u32> = HashMap::new();
-    let mut offset = 0;
-
-    // Pass 1: Collect label definitions
-    source.retain(|stmt| {
-        match stmt.value {
-            // Store label name and current offset
-            Statement::Label(ref name) => {
-                if labels.insert(name.clone(), offset).is_some() {
-                    warn!("redefinition of label: {:?}", name; stmt);
-                }
-
-                false  // Remove label definition from the source
-            },
-
-            // Increment the offset (only operation statements will count
-            // in the final binary)
-            Statement::Operation(_, ref args) => {
-                offset += 1 + args.len() as u32;
-                true  // Not a label definition, keep it
-            },
-
-            _ => true  // Something else, keep it
-        }
-    });
-
-    debug!("Labels: {:?}", labels);
-
-    // Pass 2: Replace label usages
-    for stmt in source.iter_mut() {
-
-        // Process all operations
-        if let Statement::Operation(_, ref mut args) = stmt.value {
-            for arg in args.iter_mut() {
-
-                // Get a new location if argument is a label
-                arg.value = if let Argument::Label(ref name) = arg.value {
-
-                    if let Some(val) = labels.get(name) {
-                        Argument::Literal(overflow_check!(*val, arg))
-                    } else {
-                        fatal!("unknown label: {:?}", name; arg)
-                    }
-
-                } else {
-                    continue
-                }
-
-            }
-        }
-    }
-}
\ No newline at end of file
+//! A syntax extension that replaces labels with the referenced instruction number
+//!
+//! # Example:
+//!
+//! ```
+//! label:
+//! GOTO :label
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! GOTO 0
+//! ```
+//!
+//! # Note:
+//!
+//! In object files, labels that aren't defined in the file are left for the
+//! linker. All bytes containing a label address are recorded as relocations.
+//! A label right after a program that fills the whole memory is an error.
+
+use std::collections::{HashMap, HashSet};
+use assembler::parser::ast::{Program, Statement, Argument, Ident};
+use assembler::parser::syntax_ext::{Context, Relocation};
+use machine::WordSize;
+
+
+pub fn expand(source: &mut Program, ctx: &mut Context) {
+    let mut labels: HashMap<Ident, u32> = HashMap::new();
+    let mut local_labels = HashSet::new();
+    let mut offset = 0;
+
+    // Pass 1: Collect label definitions
+    source.retain(|stmt| {
+        match stmt.value {
+            // Store label name and current offset
+            Statement::Label(ref name) => {
+                if labels.insert(name.clone(), offset).is_some() {
+                    warn!("redefinition of label: {:?}", name; stmt);
+                }
+
+                // Generated labels aren't visible to other object files
+                if &**stmt.location.filename == "<internal>" || name.as_str().contains(".") {
+                    local_labels.insert(name.clone());
+                }
+
+                false  // Remove label definition from the source
+            },
+
+            // Increment the offset (only operation statements will count
+            // in the final binary)
+            Statement::Operation(_, ref args) => {
+                offset += 1 + args.len() as u32;
+                true  // Not a label definition, keep it
+            },
+
+            _ => true  // Something else, keep it
+        }
+    });
+
+    debug!("Labels: {:?}", labels);
+
+    // Pass 2: Replace label usages
+    let mut offset = 0;
+    for stmt in source.iter_mut() {
+
+        // Process all operations
+        if let Statement::Operation(_, ref mut args) = stmt.value {
+            let position = offset;
+            offset += 1 + args.len();
+
+            for (i, arg) in args.iter_mut().enumerate() {
+
+                // Get a new location if argument is a label
+                arg.value = if let Argument::Label(ref name) = arg.value {
+
+                    if let Some(val) = labels.get(name) {
+                        if ctx.object {
+                            ctx.relocations.push(Relocation::Code(position + 1 + i));
+                        }
+
+                        // A label behind the last byte of memory can't be jumped to
+                        if *val > 255 {
+                            fatal!("label :{} is out of range: {} > 255", name, val; arg)
+                        }
+
+                        Argument::Literal(*val as WordSize)
+                    } else if ctx.object {
+                        // Defined in another object file
+                        ctx.relocations.push(Relocation::External(position + 1 + i, name.clone(),
+                                                                  arg.location.clone()));
+                        Argument::Literal(0)
+                    } else {
+                        fatal!("unknown label: {:?}", name; arg)
+                    }
+
+                } else {
+                    continue
+                }
+
+            }
+        }
+    }
+
+    // Keep the offsets for later passes
+    ctx.labels = labels;
+    ctx.local_labels = local_labels;
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::borrow::ToOwned;
+    use assembler::parser::{Parser, Context};
+
+    /// A program that jumps to a label after `size` bytes
+    fn jump_to(size: usize) -> Vec<String> {
+        let mut source = "JMP :end\n".to_owned();
+        for _ in 0 .. (size - 2) / 3 {
+            source.push_str("MOV [0] 1\n");
+        }
+        for _ in 0 .. (size - 2) % 3 {
+            source.push_str("HALT\n");
+        }
+        source.push_str("end:\n");
+
+        let mut program = Parser::new(&source, "test.asm").parse();
+        super::expand(&mut program, &mut Context::new());
+
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(jump_to(8), vec!["JMP 8", "MOV [0] 1", "MOV [0] 1"]);
+        assert_eq!(jump_to(255)[0], "JMP 255");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_label_out_of_range() {
+        jump_to(256);
+    }
+}
@@ -1,15 +1,126 @@
-use assembler::parser::ast::Program;
-
-mod imports;
-mod subroutines;
-mod auto_address;
-mod constants;
-mod labels;
-
-pub fn expand_syntax_extensions(source: &mut Program) {
-    imports::expand(source);
-    subroutines::expand(source);
-    auto_address::expand(source);
-    constants::expand(source);
-    labels::expand(source);
-}
+use std::collections::{HashMap, HashSet};
+use assembler::parser::ast::{Program, Statement, StatementNode, Argument, Ident, Mnemonic};
+use assembler::parser::SourceLocation;
+use machine::WordSize;
+
+pub use self::auto_address::Allocation;
+pub use self::subroutines::{InlineSite, RemovedRoutine, EmittedRoutine, ExternalCall, Frame};
+pub use self::size::SizeReport;
+pub use self::peephole::Optimization;
+pub use self::flow_graph::FlowGraph;
+
+mod expressions;
+mod conditionals;
+mod imports;
+mod repeat;
+mod control_flow;
+mod pseudo_ops;
+mod data;
+mod subroutines;
+mod auto_address;
+mod constants;
+mod peephole;
+mod flow_graph;
+mod labels;
+mod assertions;
+mod unused;
+mod size;
+
+
+/// A byte of an object file the linker has to adjust
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Relocation {
+    Code(usize),                            // A program address inside the object
+    Data(usize, Ident),                     // The data address of an auto-allocated constant
+    External(usize, Ident, SourceLocation)  // The address of a label from another object
+}
+
+
+/// Information collected while expanding the syntax extensions
+pub struct Context {
+    pub object: bool,                       // Whether we're building an object file (`-c`)
+    pub optimize: bool,                     // Whether to run the peephole optimizer (`-O`)
+    pub defines: HashMap<Ident, WordSize>,  // Constants defined on the command line
+    pub imports: Vec<String>,               // The files that have been imported
+    pub inlined: Vec<InlineSite>,
+    pub removed: Vec<RemovedRoutine>,
+    pub emitted: Vec<EmittedRoutine>,
+    pub frames: Vec<Frame>,
+    pub memory_map: Vec<Allocation>,
+    pub constants: HashMap<Ident, Argument>,    // The values of all constants
+    pub labels: HashMap<Ident, u32>,             // The offsets of all labels
+    pub referenced: HashSet<Ident>,              // Constants used by `#if` and `@repeat`
+    pub size: SizeReport,
+    pub local_labels: HashSet<Ident>,           // Labels generated by the syntax extensions
+    pub relocations: Vec<Relocation>,           // Only collected for object files
+    pub external_calls: Vec<ExternalCall>,
+    pub optimized: Vec<Optimization>,
+    pub flow_graph: FlowGraph
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            object: false,
+            optimize: false,
+            defines: HashMap::new(),
+            imports: vec![],
+            inlined: vec![],
+            removed: vec![],
+            emitted: vec![],
+            frames: vec![],
+            memory_map: vec![],
+            constants: HashMap::new(),
+            labels: HashMap::new(),
+            referenced: HashSet::new(),
+            size: SizeReport::new(),
+            local_labels: HashSet::new(),
+            relocations: vec![],
+            external_calls: vec![],
+            optimized: vec![],
+            flow_graph: FlowGraph::new()
+        }
+    }
+}
+
+
+pub fn expand_syntax_extensions(source: &mut Program, ctx: &mut Context) {
+    conditionals::expand(source, ctx);
+    imports::expand(source, ctx);
+    repeat::expand(source, ctx);
+    control_flow::expand(source);
+    pseudo_ops::expand(source);
+    data::expand(source);
+    subroutines::expand(source, ctx);
+    auto_address::expand(source, ctx);
+    unused::check(source, ctx);
+    constants::expand(source, ctx);
+    peephole::optimize(source, ctx);
+    flow_graph::check(source, ctx);
+    size::check(source, ctx);
+    labels::expand(source, ctx);
+    assertions::expand(source, ctx);
+}
+
+
+/// Build an operation statement (for the syntax extensions that generate code)
+pub fn operation(mnem: &str, args: Vec<Argument>, location: &SourceLocation) -> StatementNode {
+    let args = args.into_iter()
+        .map(|arg| Argument::new(arg, location.clone()))
+        .collect();
+
+    Statement::new(Statement::Operation(Mnemonic(mnem.parse().unwrap()), args),
+                   location.clone())
+}
+
+/// Parse a program and expand all syntax extensions (for the tests)
+#[cfg(test)]
+pub fn expand_source(source: &str) -> (Program, Context) {
+    use assembler::parser::Parser;
+
+    let mut program = Parser::new(source, "test.asm").parse();
+    let mut ctx = Context::new();
+    expand_syntax_extensions(&mut program, &mut ctx);
+
+    (program, ctx)
+}
@@ -0,0 +1,386 @@
+//! A peephole optimizer (`tiny asm -O`)
+//!
+//! # Example:
+//!
+//! ```
+//! MOV $y 7            ; Overwritten before it's read
+//! MOV $y 3
+//! MOV $x $x           ; Doesn't change anything
+//! ADD $x 0            ; Neither does this (same for `SUB $x 0`)
+//! JEQ :skip $x 5
+//! JMP :next           ; Jumps to the next instruction
+//! next:
+//! ADD $y 1
+//! skip:
+//! JMP :done
+//! ...
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! MOV $y 3
+//! JEQ :done $x 5      ; Jumps to the target of the `JMP` directly
+//! ADD $y 1
+//! skip:
+//! JMP :done
+//! ...
+//! ```
+//!
+//! The most common overwritten value is the `MOV $return 0` at the start of a
+//! subroutine that sets `$return` right away.
+//!
+//! # Note:
+//!
+//! The optimizer runs after the constants have been resolved (so `$x` and
+//! `[0]` are known to be the same) but before the labels, so the labels get
+//! their new offsets. Programs that jump to a numeric address (like `JMP 12`)
+//! aren't optimized, as the address would change. The same goes for code
+//! addresses that aren't written as labels.
+
+use std::collections::HashSet;
+use std::mem;
+use assembler::parser::ast::{Program, Statement, Argument, Ident, Mnemonic};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::{Context, Relocation};
+use assembler::parser::syntax_ext::subroutines::routine_at;
+use machine::Mnemonic as Instruction;
+use machine::WordSize;
+
+
+/// A change made by the optimizer
+pub struct Optimization {
+    pub location: SourceLocation,
+    pub description: String,
+    pub saved: usize            // Bytes
+}
+
+
+pub fn optimize(source: &mut Program, ctx: &mut Context) {
+    if !ctx.optimize {
+        return
+    }
+
+    // Numeric jump targets would point to the wrong instruction afterwards
+    if let Some(stmt) = source.iter().find(|stmt| jumps_to_number(&stmt.value)) {
+        warn!("not optimizing the program as it jumps to a fixed address: {}", stmt; stmt);
+        return
+    }
+
+    // Every change can enable another one
+    while thread_jumps(source, ctx) || remove_useless(source, ctx) {}
+}
+
+
+/// Let jumps to an unconditional `JMP :label` jump to `:label` directly
+fn thread_jumps(source: &mut Program, ctx: &mut Context) -> bool {
+    let mut changed = false;
+
+    for i in 0 .. source.len() {
+        let target = match source[i].value {
+            Statement::Operation(Mnemonic(instr), ref args) if is_jump(instr) => {
+                match args[0].value {
+                    Argument::Label(ref name) => name.clone(),
+                    _ => continue
+                }
+            },
+            _ => continue
+        };
+
+        let final_target = match follow_jumps(source, &target) {
+            Some(name) => name,
+            None => continue
+        };
+
+        if let Statement::Operation(_, ref mut args) = source[i].value {
+            args[0].value = Argument::Label(final_target.clone());
+        }
+
+        ctx.optimized.push(Optimization {
+            location: source[i].location.clone(),
+            description: format!("jump to :{} goes to :{} directly", target, final_target),
+            saved: 0
+        });
+        changed = true;
+    }
+
+    changed
+}
+
+/// Follow a chain of unconditional jumps starting at a label. Returns `None` if
+/// the label isn't followed by a `JMP :label` (or the jumps form a loop).
+fn follow_jumps(source: &Program, start: &Ident) -> Option<Ident> {
+    let mut visited = HashSet::new();
+    let mut label = start.clone();
+
+    loop {
+        visited.insert(label.clone());
+
+        let next = source.iter().position(|stmt| stmt.value == Statement::Label(label.clone()))
+            .and_then(|i| next_operation(source, i))
+            .and_then(|i| match source[i].value {
+                Statement::Operation(Mnemonic(Instruction::JMP), ref args) => {
+                    match args[0].value {
+                        Argument::Label(ref name) => Some(name.clone()),
+                        _ => None
+                    }
+                },
+                _ => None
+            });
+
+        match next {
+            Some(ref name) if visited.contains(name) => return None,
+            Some(name) => label = name,
+            None => break
+        }
+    }
+
+    if label != *start { Some(label) } else { None }
+}
+
+
+/// Remove instructions that don't have any effect
+fn remove_useless(source: &mut Program, ctx: &mut Context) -> bool {
+    let mut changed = false;
+    let mut offset = 0;
+    let mut i = 0;
+
+    while i < source.len() {
+        let size = source[i].value.size();
+        if size == 0 {
+            i += 1;
+            continue
+        }
+
+        if let Some(description) = is_useless(source, i) {
+            // Keep the size of the subroutine up to date
+            let routine = routine_at(source, &ctx.emitted, i);
+            if let Some(routine) = routine {
+                ctx.emitted[routine].size -= size;
+            }
+
+            let stmt = source.remove(i);
+            remove_relocations(&mut ctx.relocations, offset, size);
+
+            ctx.optimized.push(Optimization {
+                location: stmt.location.clone(),
+                description: format!("removed `{}` ({})", stmt, description),
+                saved: size
+            });
+            changed = true;
+        } else {
+            offset += size;
+            i += 1;
+        }
+    }
+
+    changed
+}
+
+/// Get the reason why the operation at `i` can be removed (if it can)
+fn is_useless(source: &Program, i: usize) -> Option<&'static str> {
+    let (instr, args) = match source[i].value {
+        Statement::Operation(Mnemonic(instr), ref args) => (instr, args),
+        _ => return None
+    };
+
+    let first = args.get(0).map(|arg| &arg.value);
+    let second = args.get(1).map(|arg| &arg.value);
+
+    match (instr, first, second) {
+        (Instruction::MOV, Some(&Argument::Address(a)), Some(&Argument::Address(b))) if a == b => {
+            Some("moves a value onto itself")
+        },
+        (Instruction::ADD, _, Some(&Argument::Literal(0)))
+            | (Instruction::SUB, _, Some(&Argument::Literal(0))) => {
+            Some("doesn't change the value")
+        },
+        (Instruction::JMP, Some(&Argument::Label(ref name)), _) => {
+            // Only labels may be between the jump and its target
+            let end = next_operation(source, i + 1).unwrap_or(source.len());
+            let label = Statement::Label(name.clone());
+
+            if source[i + 1 .. end].iter().any(|stmt| stmt.value == label) {
+                Some("jumps to the next instruction")
+            } else {
+                None
+            }
+        },
+        (Instruction::MOV, Some(&Argument::Address(Some(a))), _)
+            | (Instruction::RANDOM, Some(&Argument::Address(Some(a))), _) => {
+            if is_overwritten(source, i, a) {
+                Some("the value is overwritten before it's read")
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+/// Whether the address written at `i` is written again before it's read. Only
+/// looks at the instructions that are always executed after `i`.
+fn is_overwritten(source: &Program, i: usize, address: WordSize) -> bool {
+    for stmt in source[i + 1 ..].iter() {
+        let (instr, args) = match stmt.value {
+            Statement::Operation(Mnemonic(instr), ref args) => (instr, args),
+            _ => return false   // A label, the code may be reached from somewhere else
+        };
+        let write_only = instr == Instruction::MOV || instr == Instruction::RANDOM;
+
+        let reads = args.iter().enumerate().any(|(j, arg)| {
+            arg.value == Argument::Address(Some(address)) && !(write_only && j == 0)
+        });
+        if reads {
+            return false
+        }
+
+        if write_only && args[0].value == Argument::Address(Some(address)) {
+            return true
+        }
+
+        if is_jump(instr) || instr == Instruction::HALT {
+            return false
+        }
+    }
+
+    false
+}
+
+
+/// Drop the relocations of a removed instruction and move the ones after it
+pub fn remove_relocations(relocations: &mut Vec<Relocation>, offset: usize, size: usize) {
+    // Only data relocations exist before the labels are resolved
+    *relocations = mem::replace(relocations, vec![]).into_iter()
+        .filter_map(|relocation| match relocation {
+            Relocation::Data(position, _) if position >= offset && position < offset + size => {
+                None
+            },
+            Relocation::Data(position, name) if position >= offset + size => {
+                Some(Relocation::Data(position - size, name))
+            },
+            relocation => Some(relocation)
+        })
+        .collect();
+}
+
+/// Find the first operation at or after `start`
+fn next_operation(source: &Program, start: usize) -> Option<usize> {
+    (start .. source.len()).find(|&i| match source[i].value {
+        Statement::Operation(..) => true,
+        _ => false
+    })
+}
+
+pub fn is_jump(instr: Instruction) -> bool {
+    match instr {
+        Instruction::JMP | Instruction::JZ | Instruction::JEQ | Instruction::JLS
+            | Instruction::JGT => true,
+        _ => false
+    }
+}
+
+pub fn jumps_to_number(stmt: &Statement) -> bool {
+    match *stmt {
+        Statement::Operation(Mnemonic(instr), ref args) if is_jump(instr) => {
+            match args[0].value {
+                Argument::Literal(_) | Argument::Char(_) => true,
+                _ => false
+            }
+        },
+        _ => false
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::codegen::generate_binary;
+    use assembler::parser::{Parser, Context, expand_syntax_extensions};
+    use vm;
+
+    fn assemble(source: &str, optimize: bool) -> (Vec<Vec<u8>>, usize) {
+        let mut program = Parser::new(source, "test.asm").parse();
+        let mut ctx = Context::new();
+        ctx.optimize = optimize;
+
+        expand_syntax_extensions(&mut program, &mut ctx);
+        let saved = ctx.optimized.iter().fold(0, |saved, opt| saved + opt.saved);
+
+        (generate_binary(program), saved)
+    }
+
+    #[test]
+    fn test_patterns() {
+        let source = "$x = [0]\n\
+                      $y = [1]\n\
+                      MOV $y 7\n\
+                      MOV $x 5\n\
+                      MOV $y 3\n\
+                      MOV $x $x\n\
+                      ADD $x 0\n\
+                      JEQ :skip $x 5\n\
+                      JMP :next\n\
+                      next:\n\
+                      ADD $y 1\n\
+                      skip:\n\
+                      JMP :done\n\
+                      done:\n\
+                      HALT\n";
+
+        let (binary, saved) = assemble(source, true);
+        assert_eq!(binary, vec![vec![0x08, 0x00, 0x05],
+                                vec![0x08, 0x01, 0x03],
+                                vec![0x17, 0x0d, 0x00, 0x05],
+                                vec![0x0b, 0x01, 0x01],
+                                vec![0xff]]);
+        assert_eq!(saved, 13);
+    }
+
+    #[test]
+    fn test_vm() {
+        let source = "$total = [_]\n\
+                      $i = [_]\n\
+                      MOV $i 5\n\
+                      loop:\n\
+                      @call(add, $total, $i -> $total)\n\
+                      SUB $i 1\n\
+                      JEQ :done $i 0\n\
+                      JMP :loop\n\
+                      done:\n\
+                      HALT\n\
+                      @start(add, 2)\n\
+                      MOV $return $arg0\n\
+                      ADD $return $arg1\n\
+                      @end()\n";
+
+        let run = |binary: Vec<Vec<u8>>| {
+            let code: Vec<u8> = binary.into_iter().flat_map(|stmt| stmt.into_iter()).collect();
+            vm::run(&code, 0)
+        };
+
+        let (plain, _) = assemble(source, false);
+        let (optimized, saved) = assemble(source, true);
+
+        assert!(saved > 0);
+        assert_eq!(run(plain), run(optimized));
+    }
+
+    #[test]
+    fn test_routine_size() {
+        let mut program = Parser::new("@call(add, 1, 2)\n\
+                                       HALT\n\
+                                       @start(add, 2)\n\
+                                       MOV $return $arg0\n\
+                                       ADD $return $arg1\n\
+                                       @end()\n", "test.asm").parse();
+        let mut ctx = Context::new();
+        ctx.optimize = true;
+        expand_syntax_extensions(&mut program, &mut ctx);
+
+        // The `MOV $return 0` at the start of the subroutine has been removed
+        assert_eq!(ctx.emitted[0].size, 8);
+        assert_eq!(ctx.size.subroutines, vec![("(main program)".to_string(), 12),
+                                              ("add".to_string(), 8)]);
+    }
+}
@@ -0,0 +1,395 @@
+//! A syntax extension for pseudo instructions
+//!
+//! # Example:
+//!
+//! ```
+//! INC $a
+//! DEC $a
+//! CLR $a
+//! JNE :label $a $b
+//! JGE :label $a $b
+//! JLE :label $a $b
+//! JNZ :label $a
+//! SWAP $a $b
+//! JLS.S :label $a -1
+//! DPRINT.S $a
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! $pseudo.tmp0 = [_]
+//!
+//! ADD $a 1
+//! SUB $a 1
+//! MOV $a 0
+//! JEQ :pseudo0.skip $a $b
+//! JMP :label
+//! pseudo0.skip:
+//! JGT :label $a $b
+//! JEQ :label $a $b
+//! JLS :label $a $b
+//! JEQ :label $a $b
+//! JGT :label $a 0
+//! MOV $pseudo.tmp0 $a
+//! MOV $a $b
+//! MOV $b $pseudo.tmp0
+//! MOV $pseudo.tmp0 $a
+//! XOR $pseudo.tmp0 128
+//! JLS :label $pseudo.tmp0 127
+//! JLS :pseudo1.positive $a 128
+//! APRINT '-'
+//! MOV $pseudo.tmp0 0
+//! SUB $pseudo.tmp0 $a
+//! DPRINT $pseudo.tmp0
+//! JMP :pseudo1.end
+//! pseudo1.positive:
+//! DPRINT $a
+//! pseudo1.end:
+//! ```
+//!
+//! # Note:
+//!
+//! The signed comparisons flip the sign bit of both values which maps
+//! `-128 ... 127` to `0 ... 255` in the same order. Then the unsigned
+//! comparison gives the signed result.
+//!
+//! Signed comparisons of two literals and `DPRINT.S` of a literal are
+//! evaluated by the assembler.
+//!
+//! The temporary cells are allocated like any other `[_]` constant and shared
+//! by all pseudo instructions.
+
+use std::mem;
+use assembler::parser::ast::{Program, Statement, Argument, Ident, PseudoMnemonic};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::operation;
+use machine::WordSize;
+
+
+pub fn expand(source: &mut Program) {
+    let mut next_id = 0;
+    let mut temps = 0;            // The number of temporary cells needed
+    let mut tmp_location = None;  // Where a temporary cell is needed first
+
+    for stmt in mem::replace(source, vec![]).into_iter() {
+        let pseudo_op = match stmt.value {
+            Statement::PseudoOp(mnem, ref args) => Some((mnem, args.clone())),
+            _ => None
+        };
+
+        let (mnem, args) = match pseudo_op {
+            Some(pseudo_op) => pseudo_op,
+            None => {
+                source.push(stmt);
+                continue
+            }
+        };
+
+        let argc = match mnem {
+            PseudoMnemonic::INC | PseudoMnemonic::DEC | PseudoMnemonic::CLR => 1,
+            PseudoMnemonic::JNZ | PseudoMnemonic::SWAP => 2,
+            PseudoMnemonic::JNE | PseudoMnemonic::JGE | PseudoMnemonic::JLE => 3,
+            PseudoMnemonic::JLS_S | PseudoMnemonic::JGT_S => 3,
+            PseudoMnemonic::DPRINT_S => 1
+        };
+
+        if args.len() != argc {
+            fatal!("{:?} expects {} args, found {}", mnem, argc, args.len(); stmt)
+        }
+
+        let args: Vec<Argument> = args.into_iter().map(|arg| arg.value).collect();
+        let location = &stmt.location;
+
+        match mnem {
+            PseudoMnemonic::INC => {
+                source.push(operation("ADD", vec![args[0].clone(), Argument::Literal(1)], location));
+            },
+            PseudoMnemonic::DEC => {
+                source.push(operation("SUB", vec![args[0].clone(), Argument::Literal(1)], location));
+            },
+            PseudoMnemonic::CLR => {
+                source.push(operation("MOV", vec![args[0].clone(), Argument::Literal(0)], location));
+            },
+            PseudoMnemonic::JNE => {
+                // Skip the jump if the values are equal
+                let skip = Ident(format!("pseudo{}.skip", next_id));
+                next_id += 1;
+
+                source.push(operation("JEQ", vec![Argument::Label(skip.clone()),
+                                                  args[1].clone(), args[2].clone()], location));
+                source.push(operation("JMP", vec![args[0].clone()], location));
+                source.push(Statement::new(Statement::Label(skip), location.clone()));
+            },
+            PseudoMnemonic::JGE | PseudoMnemonic::JLE => {
+                let compare = if mnem == PseudoMnemonic::JGE { "JGT" } else { "JLS" };
+
+                source.push(operation(compare, args.clone(), location));
+                source.push(operation("JEQ", args, location));
+            },
+            PseudoMnemonic::JNZ => {
+                // Bytes are unsigned, so everything but 0 is greater than 0
+                source.push(operation("JGT", vec![args[0].clone(), args[1].clone(),
+                                                  Argument::Literal(0)], location));
+            },
+            PseudoMnemonic::SWAP => {
+                let tmp = Argument::Const(tmp_name(0));
+                source.push(operation("MOV", vec![tmp.clone(), args[0].clone()], location));
+                source.push(operation("MOV", vec![args[0].clone(), args[1].clone()], location));
+                source.push(operation("MOV", vec![args[1].clone(), tmp], location));
+            },
+            PseudoMnemonic::JLS_S | PseudoMnemonic::JGT_S => {
+                let less = mnem == PseudoMnemonic::JLS_S;
+
+                // Compare two literals right away (no temporary cells needed)
+                if let (Some(lhs), Some(rhs)) = (literal_value(&args[1]), literal_value(&args[2])) {
+                    let (lhs, rhs) = (lhs as i8, rhs as i8);
+                    if (less && lhs < rhs) || (!less && lhs > rhs) {
+                        source.push(operation("JMP", vec![args[0].clone()], location));
+                    }
+
+                    continue
+                }
+
+                // The first operand of a comparison can't be a literal
+                let (less, lhs, rhs) = match args[1] {
+                    Argument::Literal(_) | Argument::Char(_) => (!less, &args[2], &args[1]),
+                    _ => (less, &args[1], &args[2])
+                };
+
+                let lhs = flip_sign_bit(lhs, 0, source, location);
+                let rhs = flip_sign_bit(rhs, 1, source, location);
+                let compare = if less { "JLS" } else { "JGT" };
+
+                source.push(operation(compare, vec![args[0].clone(), lhs, rhs], location));
+            },
+            PseudoMnemonic::DPRINT_S => {
+                // Print a literal right away (no temporary cells needed)
+                if let Some(value) = literal_value(&args[0]) {
+                    if value >= 128 {
+                        let abs = (256 - value as u16) as WordSize;
+                        source.push(operation("APRINT", vec![Argument::Char('-' as WordSize)],
+                                              location));
+                        source.push(operation("DPRINT", vec![Argument::Literal(abs)], location));
+                    } else {
+                        source.push(operation("DPRINT", vec![args[0].clone()], location));
+                    }
+
+                    continue
+                }
+
+                // Print a minus and the absolute value for values >= 128
+                let prefix = Ident(format!("pseudo{}", next_id));
+                next_id += 1;
+
+                let label = |name: &str| Ident(format!("{}.{}", prefix, name));
+                let tmp = Argument::Const(tmp_name(0));
+
+                source.push(operation("JLS", vec![Argument::Label(label("positive")),
+                                                  args[0].clone(), Argument::Literal(128)],
+                                      location));
+                source.push(operation("APRINT", vec![Argument::Char('-' as WordSize)], location));
+                source.push(operation("MOV", vec![tmp.clone(), Argument::Literal(0)], location));
+                source.push(operation("SUB", vec![tmp.clone(), args[0].clone()], location));
+                source.push(operation("DPRINT", vec![tmp], location));
+                source.push(operation("JMP", vec![Argument::Label(label("end"))], location));
+                source.push(Statement::new(Statement::Label(label("positive")), location.clone()));
+                source.push(operation("DPRINT", vec![args[0].clone()], location));
+                source.push(Statement::new(Statement::Label(label("end")), location.clone()));
+            }
+        }
+
+        // Remember which temporary cells are needed
+        let needed = match mnem {
+            PseudoMnemonic::SWAP | PseudoMnemonic::DPRINT_S => 1,
+            PseudoMnemonic::JLS_S | PseudoMnemonic::JGT_S => 2,
+            _ => 0
+        };
+
+        if needed > temps {
+            temps = needed;
+        }
+        if needed > 0 && tmp_location.is_none() {
+            tmp_location = Some(stmt.location.clone());
+        }
+    }
+
+    if let Some(location) = tmp_location {
+        for i in 0 .. temps {
+            source.insert(i, Statement::new(
+                Statement::Const(tmp_name(i),
+                                 Argument::new(Argument::Address(None), location.clone())),
+                location.clone()
+            ));
+        }
+    }
+}
+
+
+fn tmp_name(n: usize) -> Ident {
+    Ident(format!("pseudo.tmp{}", n))
+}
+
+/// Get the value of a literal argument
+fn literal_value(arg: &Argument) -> Option<WordSize> {
+    match *arg {
+        Argument::Literal(i) | Argument::Char(i) => Some(i),
+        _ => None
+    }
+}
+
+/// Get the value with the sign bit flipped (uses temporary cell `n` unless
+/// the value is a literal)
+fn flip_sign_bit(value: &Argument, n: usize, source: &mut Program,
+                 location: &SourceLocation) -> Argument {
+    match *value {
+        Argument::Literal(i) => Argument::Literal(i ^ 0x80),
+        Argument::Char(c) => Argument::Literal(c ^ 0x80),
+        _ => {
+            let tmp = Argument::Const(tmp_name(n));
+            source.push(operation("MOV", vec![tmp.clone(), value.clone()], location));
+            source.push(operation("XOR", vec![tmp.clone(), Argument::Literal(0x80)], location));
+
+            tmp
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::codegen::generate_binary;
+    use assembler::parser::Parser;
+    use assembler::parser::syntax_ext::expand_source;
+    use vm;
+
+    fn expand(source: &str) -> Vec<String> {
+        let mut program = Parser::new(source, "test.asm").parse();
+        super::expand(&mut program);
+
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_inc() {
+        assert_eq!(expand("INC $a\n"), vec!["ADD $a 1"]);
+    }
+
+    #[test]
+    fn test_dec() {
+        assert_eq!(expand("DEC $a\n"), vec!["SUB $a 1"]);
+    }
+
+    #[test]
+    fn test_clr() {
+        assert_eq!(expand("CLR $a\n"), vec!["MOV $a 0"]);
+    }
+
+    #[test]
+    fn test_jne() {
+        assert_eq!(expand("JNE :l $a $b\n\
+                           JNE :l $a 5\n"),
+                   vec!["JEQ :pseudo0.skip $a $b", "JMP :l", "pseudo0.skip:",
+                        "JEQ :pseudo1.skip $a 5", "JMP :l", "pseudo1.skip:"]);
+    }
+
+    #[test]
+    fn test_jge() {
+        assert_eq!(expand("JGE :l $a $b\n"), vec!["JGT :l $a $b", "JEQ :l $a $b"]);
+    }
+
+    #[test]
+    fn test_jle() {
+        assert_eq!(expand("JLE :l $a 3\n"), vec!["JLS :l $a 3", "JEQ :l $a 3"]);
+    }
+
+    #[test]
+    fn test_jnz() {
+        assert_eq!(expand("JNZ :l $a\n"), vec!["JGT :l $a 0"]);
+    }
+
+    #[test]
+    fn test_swap() {
+        assert_eq!(expand("SWAP $a $b\n\
+                           SWAP $b $c\n"),
+                   vec!["$pseudo.tmp0 = [_]",
+                        "MOV $pseudo.tmp0 $a", "MOV $a $b", "MOV $b $pseudo.tmp0",
+                        "MOV $pseudo.tmp0 $b", "MOV $b $c", "MOV $c $pseudo.tmp0"]);
+    }
+
+    #[test]
+    fn test_signed_compare() {
+        assert_eq!(expand("JLS.S :l $a $b\n"),
+                   vec!["$pseudo.tmp0 = [_]", "$pseudo.tmp1 = [_]",
+                        "MOV $pseudo.tmp0 $a", "XOR $pseudo.tmp0 128",
+                        "MOV $pseudo.tmp1 $b", "XOR $pseudo.tmp1 128",
+                        "JLS :l $pseudo.tmp0 $pseudo.tmp1"]);
+
+        // Literals are flipped right away, -1 becomes 127
+        assert_eq!(expand("JGT.S :l $a -1\n")[2 ..].to_vec(),
+                   vec!["MOV $pseudo.tmp0 $a", "XOR $pseudo.tmp0 128",
+                        "JGT :l $pseudo.tmp0 127"]);
+    }
+
+    #[test]
+    fn test_signed_compare_literal_lhs() {
+        // `5 > $a` is checked as `$a < 5`
+        assert_eq!(expand("JGT.S :l 5 $a\n")[2 ..].to_vec(),
+                   vec!["MOV $pseudo.tmp0 $a", "XOR $pseudo.tmp0 128",
+                        "JLS :l $pseudo.tmp0 133"]);
+    }
+
+    #[test]
+    fn test_signed_compare_literals() {
+        assert_eq!(expand("JLS.S :l -1 1\n"), vec!["JMP :l"]);
+        assert!(expand("JGT.S :l -1 1\n").is_empty());
+        assert_eq!(expand("JGT.S :l 1 -128\n"), vec!["JMP :l"]);
+    }
+
+    #[test]
+    fn test_signed_print() {
+        let code = expand("DPRINT.S $a\n");
+        assert_eq!(code[1], "JLS :pseudo0.positive $a 128");
+        assert_eq!(code[3 ..].to_vec(),
+                   vec!["MOV $pseudo.tmp0 0", "SUB $pseudo.tmp0 $a", "DPRINT $pseudo.tmp0",
+                        "JMP :pseudo0.end", "pseudo0.positive:", "DPRINT $a", "pseudo0.end:"]);
+    }
+
+    #[test]
+    fn test_signed_print_literal() {
+        assert_eq!(expand("DPRINT.S 5\n"), vec!["DPRINT 5"]);
+        assert_eq!(expand("DPRINT.S -1\n")[1 ..].to_vec(), vec!["DPRINT 1"]);
+        assert_eq!(expand("DPRINT.S -128\n")[1 ..].to_vec(), vec!["DPRINT 128"]);
+    }
+
+    #[test]
+    fn test_signed_print_vm() {
+        // The absolute value that gets printed is left in the temporary cell
+        let absolute = |value: i32| {
+            let (program, ctx) = expand_source(&format!("$a = [0]\n\
+                                                         MOV $a {}\n\
+                                                         DPRINT.S $a\n\
+                                                         HALT\n", value));
+            let tmp = ctx.memory_map.iter()
+                .find(|alloc| {
+                    alloc.symbol.as_ref().map_or(false, |name| name.as_str() == "pseudo.tmp0")
+                })
+                .unwrap()
+                .address;
+
+            let code: Vec<u8> = generate_binary(program).into_iter()
+                .flat_map(|stmt| stmt.into_iter())
+                .collect();
+            vm::run(&code, 0)[tmp as usize]
+        };
+
+        assert_eq!(absolute(-1), 1);
+        assert_eq!(absolute(-128), 128);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_wrong_argc() {
+        expand("INC $a $b\n");
+    }
+}
@@ -0,0 +1,362 @@
+//! A syntax extension for repeating (unrolling) a block of code
+//!
+//! # Example:
+//!
+//! ```
+//! @repeat(3, i)
+//!     bit{$i}:
+//!     ADD $sum ($i * 2)
+//! @endrepeat()
+//! ```
+//!
+//! Results in:
+//!
+//! ```
+//! bit0:
+//! ADD $sum 0
+//! bit1:
+//! ADD $sum 2
+//! bit2:
+//! ADD $sum 4
+//! ```
+//!
+//! # Note:
+//!
+//! The loop index is available as a constant inside of the block. Label and
+//! constant names can contain expressions in braces (like `bit{$i + 1}`)
+//! which are replaced by their value. The count can use literal constants.
+//! Where the index is used as a value, it has to fit into a byte.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use assembler::parser::ast::{Program, Statement, StatementNode, Argument, MacroArgument,
+                             Expression, ExpressionNode, Ident};
+use assembler::parser::{Parser, SourceLocation};
+use assembler::parser::lexer::Token;
+use assembler::parser::syntax_ext::Context;
+use assembler::parser::syntax_ext::expressions::evaluate;
+use assembler::util::fatal;
+use machine::WordSize;
+
+
+pub fn expand(source: &mut Program, ctx: &mut Context) {
+    // Literal constants that can be used for the count and in names
+    let mut consts = HashMap::new();
+    for stmt in source.iter() {
+        if let Statement::Const(ref name, ref value) = stmt.value {
+            match value.value {
+                Argument::Literal(i) | Argument::Char(i) => {
+                    consts.insert(name.clone(), i as i64);
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // Remember the constants we use (for the unused symbol lint)
+    let referenced = RefCell::new(vec![]);
+    let lookup = |name: &Ident| {
+        referenced.borrow_mut().push(name.clone());
+        consts.get(name).cloned()
+    };
+
+    // Pass 1: Unroll all blocks. Nested blocks are unrolled when we reach the
+    // copies of them.
+    let mut i = 0;
+    while i < source.len() {
+        let (count, index) = match source[i].value {
+            Statement::Macro(ref ident, ref args, _) if ident.as_str() == "repeat" => {
+                if args.len() != 2 {
+                    fatal!("expected (count, index), found {} args", args.len(); source[i])
+                }
+
+                let count = match args[0].value {
+                    MacroArgument::Argument(ref arg) => {
+                        let expr = Expression::new(Expression::Value(arg.value.clone()),
+                                                   arg.location.clone());
+                        evaluate(&expr, &lookup)
+                    },
+                    ref other => {
+                        fatal!("expected repetition count, found `{}`", other; args[0])
+                    }
+                };
+
+                let index = match args[1].value {
+                    MacroArgument::Ident(ref ident) => ident.clone(),
+                    ref other => {
+                        fatal!("expected index name, found `{}`", other; args[1])
+                    }
+                };
+
+                if count < 0 {
+                    fatal!("invalid repetition count: {}", count; args[0])
+                }
+
+                (count, index)
+            },
+            Statement::Macro(ref ident, _, _) if ident.as_str() == "endrepeat" => {
+                fatal!("@endrepeat without @repeat"; source[i])
+            },
+            _ => {
+                i += 1;
+                continue
+            }
+        };
+
+        let end = find_end(source, i);
+
+        // Remove the block from the source
+        source.remove(end);
+        let body: Vec<StatementNode> = (i + 1 .. end).map(|_| source.remove(i + 1)).collect();
+        source.remove(i);
+
+        // Insert the copies
+        let mut pos = i;
+        for n in 0 .. count {
+            for stmt in body.iter() {
+                let mut stmt = stmt.clone();
+                substitute(&mut stmt, &index, n);
+
+                source.insert(pos, stmt);
+                pos += 1;
+            }
+        }
+    }
+
+    // Pass 2: Replace the expressions in names with their value
+    for stmt in source.iter_mut() {
+        rename(stmt, &|name: &Ident, location: &SourceLocation| {
+            interpolate(name, location, &|expr: ExpressionNode| {
+                let value = evaluate(&expr, &lookup);
+                if value < 0 {
+                    fatal!("negative value in name: {}", value; expr)
+                }
+
+                format!("{}", value)
+            })
+        });
+    }
+
+    ctx.referenced.extend(referenced.borrow().iter().cloned());
+}
+
+
+/// Find the `@endrepeat` belonging to the `@repeat` at `start`
+fn find_end(source: &Program, start: usize) -> usize {
+    let mut depth = 0;
+
+    for (i, stmt) in source.iter().enumerate().skip(start) {
+        if let Statement::Macro(ref ident, _, _) = stmt.value {
+            match ident.as_str() {
+                "repeat" => depth += 1,
+                "endrepeat" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fatal!("unterminated @repeat"; source[start])
+}
+
+/// Replace the loop index in a statement with its value
+fn substitute(stmt: &mut StatementNode, index: &Ident, value: i64) {
+    for arg in stmt.value.arguments_mut().into_iter() {
+        let location = arg.location.clone();
+        substitute_argument(&mut arg.value, &location, index, value);
+    }
+
+    rename(stmt, &|name: &Ident, location: &SourceLocation| {
+        interpolate(name, location, &|mut expr: ExpressionNode| {
+            substitute_expression(&mut expr, index, value);
+            format!("{{{}}}", expr)
+        })
+    });
+}
+
+fn substitute_argument(arg: &mut Argument, location: &SourceLocation, index: &Ident, value: i64) {
+    let replace = match *arg {
+        Argument::Const(ref name) => name == index,
+        Argument::Expr(ref mut expr) => {
+            substitute_expression(expr, index, value);
+            false
+        },
+        _ => false
+    };
+
+    if replace {
+        if value > 255 {
+            fatal(format!("value out of range: {}", value), location)
+        }
+
+        *arg = Argument::Literal(value as WordSize);
+    }
+}
+
+fn substitute_expression(expr: &mut ExpressionNode, index: &Ident, value: i64) {
+    let location = expr.location.clone();
+
+    match expr.value {
+        Expression::Value(ref mut arg) => substitute_argument(arg, &location, index, value),
+        Expression::Unary(_, ref mut operand) => substitute_expression(operand, index, value),
+        Expression::Binary(_, ref mut lhs, ref mut rhs) => {
+            substitute_expression(lhs, index, value);
+            substitute_expression(rhs, index, value);
+        }
+    }
+}
+
+/// Apply `f` to all label and constant names in a statement
+fn rename<F>(stmt: &mut StatementNode, f: &F)
+        where F: Fn(&Ident, &SourceLocation) -> Ident {
+    let location = stmt.location.clone();
+
+    match stmt.value {
+        Statement::Label(ref mut name) | Statement::Const(ref mut name, _)
+                | Statement::Data(ref mut name, _, _) => {
+            let renamed = f(name, &location);
+            *name = renamed;
+        },
+        _ => {}
+    }
+
+    for arg in stmt.value.arguments_mut().into_iter() {
+        let location = arg.location.clone();
+        rename_argument(&mut arg.value, &location, f);
+    }
+}
+
+fn rename_argument<F>(arg: &mut Argument, location: &SourceLocation, f: &F)
+        where F: Fn(&Ident, &SourceLocation) -> Ident {
+    match *arg {
+        Argument::Const(ref mut name) | Argument::Label(ref mut name) => {
+            let renamed = f(name, location);
+            *name = renamed;
+        },
+        Argument::Expr(ref mut expr) => rename_expression(expr, f),
+        _ => {}
+    }
+}
+
+fn rename_expression<F>(expr: &mut ExpressionNode, f: &F)
+        where F: Fn(&Ident, &SourceLocation) -> Ident {
+    let location = expr.location.clone();
+
+    match expr.value {
+        Expression::Value(ref mut arg) => rename_argument(arg, &location, f),
+        Expression::Unary(_, ref mut operand) => rename_expression(operand, f),
+        Expression::Binary(_, ref mut lhs, ref mut rhs) => {
+            rename_expression(lhs, f);
+            rename_expression(rhs, f);
+        }
+    }
+}
+
+/// Replace all `{...}` parts of a name with the result of `rewrite`
+fn interpolate<F>(name: &Ident, location: &SourceLocation, rewrite: &F) -> Ident
+        where F: Fn(ExpressionNode) -> String {
+    let mut result = String::new();
+    let mut rest = name.as_str();
+
+    while let Some(start) = rest.find('{') {
+        // The lexer makes sure there is a closing brace
+        let end = rest.find('}').unwrap();
+        let source = &rest[start + 1 .. end];
+
+        if source.trim().len() == 0 {
+            fatal(format!("empty expression in name: {}", name), location)
+        }
+
+        let mut parser = Parser::new(source, &location.filename);
+        let expr = parser.parse_expression();
+        if parser.token != Token::EOF {
+            fatal(format!("invalid expression in name: {}", name), location)
+        }
+
+        result.push_str(&rest[.. start]);
+        result.push_str(&rewrite(expr));
+        rest = &rest[end + 1 ..];
+    }
+
+    result.push_str(rest);
+    Ident(result)
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::{Parser, Context};
+
+    fn expand(source: &str) -> Vec<String> {
+        let mut program = Parser::new(source, "test.asm").parse();
+        super::expand(&mut program, &mut Context::new());
+
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(expand("@repeat(3, i)\n\
+                           bit{$i}:\n\
+                           ADD $sum $i\n\
+                           @endrepeat()\n\
+                           HALT\n"),
+                   vec!["bit0:", "ADD $sum 0", "bit1:", "ADD $sum 1", "bit2:", "ADD $sum 2",
+                        "HALT"]);
+    }
+
+    #[test]
+    fn test_constant_count() {
+        assert_eq!(expand("$n = 2\n\
+                           @repeat(($n - 1), i)\n\
+                           DPRINT $i\n\
+                           @endrepeat()\n"),
+                   vec!["$n = 2", "DPRINT 0"]);
+    }
+
+    #[test]
+    fn test_nested_repeat() {
+        assert_eq!(expand("@repeat(2, i)\n\
+                           @repeat(2, j)\n\
+                           MOV $v{$i * 2 + $j} $j\n\
+                           @endrepeat()\n\
+                           @endrepeat()\n"),
+                   vec!["MOV $v0 0", "MOV $v1 1", "MOV $v2 0", "MOV $v3 1"]);
+    }
+
+    #[test]
+    fn test_zero_count() {
+        assert_eq!(expand("@repeat(0, i)\n\
+                           DPRINT $i\n\
+                           @endrepeat()\n\
+                           HALT\n"),
+                   vec!["HALT"]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_negative_count() {
+        expand("@repeat((0 - 1), i)\n\
+                HALT\n\
+                @endrepeat()\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_index_out_of_range() {
+        expand("@repeat((200 + 57), i)\n\
+                DPRINT $i\n\
+                @endrepeat()\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_unterminated_repeat() {
+        expand("@repeat(2, i)\n\
+                HALT\n");
+    }
+}
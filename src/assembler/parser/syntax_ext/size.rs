@@ -0,0 +1,115 @@
+//! A check that the program fits into the program memory
+//!
+//! Labels are byte offsets into the program, so a program with more than 256
+//! bytes can't be addressed completely. If the program is too large, the
+//! error contains the number of bytes each file and subroutine takes.
+
+use std::fmt;
+use assembler::parser::ast::Program;
+use assembler::parser::syntax_ext::Context;
+use machine::MEMORY_SIZE;
+
+
+/// The size of the program, broken down by file and by subroutine
+pub struct SizeReport {
+    pub total: usize,
+    pub files: Vec<(String, usize)>,        // In the order of their first statement
+    pub subroutines: Vec<(String, usize)>   // The main program comes first
+}
+
+impl SizeReport {
+    pub fn new() -> SizeReport {
+        SizeReport {
+            total: 0,
+            files: vec![],
+            subroutines: vec![]
+        }
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "Total: {} of {} bytes", self.total, MEMORY_SIZE));
+
+        try!(writeln!(f, "By file:"));
+        for &(ref file, size) in self.files.iter() {
+            try!(writeln!(f, "    {:<32} {:>4} bytes", file, size));
+        }
+
+        try!(writeln!(f, "By subroutine:"));
+        for &(ref name, size) in self.subroutines.iter() {
+            try!(writeln!(f, "    {:<32} {:>4} bytes", name, size));
+        }
+
+        Ok(())
+    }
+}
+
+
+pub fn check(source: &Program, ctx: &mut Context) {
+    let mut report = SizeReport::new();
+    let mut overflow = None;  // The first statement that doesn't fit
+
+    for stmt in source.iter() {
+        let size = stmt.value.size();
+        if size == 0 {
+            continue
+        }
+
+        report.total += size;
+        if report.total > MEMORY_SIZE && overflow.is_none() {
+            overflow = Some(stmt);
+        }
+
+        let file = &**stmt.location.filename;
+        match report.files.iter().position(|&(ref name, _)| name == file) {
+            Some(i) => report.files[i].1 += size,
+            None => report.files.push((file.to_string(), size))
+        }
+    }
+
+    let routines = ctx.emitted.iter().fold(0, |size, routine| size + routine.size);
+    let main = report.total.saturating_sub(routines);
+    report.subroutines.push(("(main program)".to_string(), main));
+    for routine in ctx.emitted.iter() {
+        report.subroutines.push((format!("{}", routine.name), routine.size));
+    }
+
+    if let Some(stmt) = overflow {
+        fatal!("the program doesn't fit into memory ({} bytes, the limit is {})\n{}",
+               report.total, MEMORY_SIZE, report; stmt)
+    }
+
+    ctx.size = report;
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::syntax_ext::expand_source;
+
+    #[test]
+    fn test_report() {
+        let (_, ctx) = expand_source("@call(double, 2)\n\
+                                      HALT\n\
+                                      @start(double, 1)\n\
+                                      MOV $return $arg0\n\
+                                      ADD $return $arg0\n\
+                                      @end()\n");
+
+        // The code of the call and the subroutine's prologue and epilogue is generated
+        assert_eq!(ctx.size.total, 20);
+        assert_eq!(ctx.size.files, vec![("<internal>".to_string(), 13),
+                                        ("test.asm".to_string(), 7)]);
+        assert_eq!(ctx.size.subroutines, vec![("(main program)".to_string(), 9),
+                                              ("double".to_string(), 11)]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_too_large() {
+        // 86 * 3 = 258 bytes
+        let source = (0 .. 86).map(|_| "MOV [0] 1\n").collect::<Vec<_>>().concat();
+        expand_source(&source);
+    }
+}
@@ -1,285 +1,1525 @@
-//! A syntax extension for custom subroutines
-//!
-//! # Example:
-//!
-//! Subroutine call:
-//!
-//! ```
-//! @call(name, arg1, arg2)
-//! ```
-//!
-//! FIXME: Maybe use @name(arg1, arg2) instead?
-//!
-//! Subroutine definition:
-//!
-//! ```
-//! @start(name, argc)
-//!    ...
-//! @end()
-//! ```
-
-use std::borrow::ToOwned;
-use std::collections::HashMap;
-use assembler::parser::ast::{Program, Statement, StatementNode, Argument, MacroArgument, MacroArgumentNode,
-                             Ident};
-use assembler::parser::Parser;
-
-use self::SubroutineState::*;
-
-
-pub fn expand(source: &mut Program) {
-    SubroutineExpander {
-        source: source,
-        routines: HashMap::new()
-    }.expand();
-}
-
-
-// --- Subroutine Expansion: Implementation -------------------------------------
-
-// We use a state machine to keep track of where we are and what is allowed.
-
-#[derive(Debug, Clone, Eq, PartialEq)]
-enum SubroutineState {
-    SubroutineStart(Ident), // Definition of a new subroutine
-    InSubroutine,               // Subroutine body
-    SubroutineEnd,              // End of the body
-    SubroutineCall(Ident, Vec<MacroArgumentNode>),  // Call of a subroutine
-    NotInSubroutine            // Everything else
-}
-
-struct SubroutineExpander<'a> {
-    source: &'a mut Program,
-    routines: HashMap<Ident, usize>
-}
-
-impl<'a> SubroutineExpander<'a> {
-
-    fn expand(&mut self) {
-        // Pass 1: Collect definitions and build preamble
-        self.collect_routines();
-        if self.routines.len() == 0 {
-            return
-        }
-
-        // Build preamble
-        self.build_preamble();
-
-        debug!("Subroutines: {:?}", self.routines);
-
-        // Pass 2: Replace function definitions
-        self.process_macros();
-
-        // Pass 3: Remove macro statements
-        self.source.retain(|stmt| {
-            match stmt.value {
-                Statement::Macro(..) => {
-                    false
-                },
-                _ => true
-            }
-        });
-    }
-
-    /// Collect all subroutine definitions and store them in `self.routines`
-    fn collect_routines(&mut self) {
-        for stmt in self.source.iter() {
-            let (ident, args) = match stmt.value {
-                Statement::Macro(ref ident, ref args) => (ident.clone(), args),
-                _ => continue
-            };
-
-            if ident.as_str() == "start" {
-                // Two args expected: name and number of arguments
-                if args.len() != 2 {
-                    fatal!("invalid number of Argument::s for @start: {}",
-                           args.len(); stmt)
-                }
-
-                let name = if let MacroArgument::Ident(ref name) = args[0].value {
-                    name.clone()
-                } else {
-                    fatal!("expected subroutine name, got {}", args[0]; stmt)
-                };
-
-                let argc = if let MacroArgument::Argument(ref arg) = args[1].value {
-                    if let Argument::Literal(argc) = arg.value {
-                        argc as usize
-                    } else {
-                        fatal!("expected argument count, got {}", args[1]; stmt)
-                    }
-                } else {
-                    fatal!("expected argument count, got {}", args[1]; stmt)
-                };
-
-                // Subroutine definition is valid, store it
-                if self.routines.insert(name, argc).is_some() {
-                    fatal!("redefinition of subroutine: {}", args[0]; stmt)
-                };
-            }
-        }
-    }
-
-    fn parse_and_insert(&mut self, source: &str, pos: usize) {
-        let ast = Parser::new(source, "<internal>").parse();
-
-        for (i, stmt) in ast.into_iter().enumerate() {
-            self.source.insert(pos + i, stmt)
-        }
-    }
-
-    /// Build the preamble for the subroutine machinery.
-    /// Will only be inserted once at
-    ///
-    /// Will look like this:
-    ///
-    /// ```
-    /// $return = [_]     ; The return value
-    /// $jump_back = [_]  ; The return address
-    /// $arg0 = [_]       ; Arguments any subroutine receives
-    /// ```
-    fn build_preamble(&mut self) {
-        let mut template = r###"
-            $return = [_]
-            $jump_back = [_]
-        "###.to_owned();
-
-        for i in 0 .. *self.routines.values().max().unwrap() {
-            template.push_str(&format!("$arg{} = [_]\n", i));
-        }
-
-        self.parse_and_insert(&template, 0);
-    }
-
-    /// Process subroutine definitions and calls
-    fn process_macros(&mut self) {
-        let mut state = NotInSubroutine;
-
-        // We use a indexed iteration here because we'll modify the source as we iterate
-        // over it
-        let mut i = 0;
-        while i < self.source.len() {
-            let prev_state = state.clone();
-
-            state = match self.get_state_for(&self.source[i], &state) {
-                /// State processing & transitions
-
-                SubroutineStart(ident) => {
-                    // Build subroutine preamble
-                    self.source.remove(i);
-
-                    let mut template = format!("{}:\n", ident);
-                    template.push_str("MOV $return 0\n");
-
-                    self.parse_and_insert(&template, i);
-
-                    InSubroutine
-                },
-
-                SubroutineEnd => {
-                    // Build subroutine epilogue
-                    self.source.remove(i);
-
-                    self.parse_and_insert("JMP $jump_back\n", i);
-
-                    NotInSubroutine
-                },
-
-                SubroutineCall(name, args) => {
-                    self.source.remove(i);
-
-                    let mut template = String::new();
-
-                    // Build arguments
-                    for j in 0 .. args.len() {
-                        let arg = match args[j].value {
-                            MacroArgument::Argument(ref arg) => arg,
-                            MacroArgument::Ident(ref ident) => {
-                                fatal!("expected argument, got `{}`", ident; args[j])
-                            }
-                        };
-
-                        template.push_str(&format!("MOV $arg{} {}\n", j, arg));
-                    }
-
-                    // Set jumpback
-                    template.push_str(&format!("MOV $jump_back :ret{}\n", i));
-
-                    // Jump to function
-                    template.push_str(&format!("JMP :{}\n", name));
-
-                    // Add label where to continue
-                    template.push_str(&format!("ret{}:\n", i));
-
-                    self.parse_and_insert(&template, i);
-
-                    prev_state  // Return to previous state
-                },
-
-                _ => state // Stay in current state
-            };
-
-            i += 1;
-        }
-    }
-
-    /// Get the current state based on the statement we're currently processing
-    fn get_state_for(&self, stmt: &StatementNode, state: &SubroutineState) -> SubroutineState {
-        match stmt.value {
-            Statement::Macro(ref ident, ref args) => {
-                match ident.as_str() {
-                    "start" => {
-                        if *state == InSubroutine { fatal!("can't nest subroutines"; stmt); }
-
-                        // Get subroutine name
-                        let ident = if let MacroArgument::Ident(ref ident) = args[0].value {
-                            ident.clone()
-                        } else {
-                            fatal!("expected subroutine name, found `{}`", args[0].value; args[0]);
-                        };
-
-                        SubroutineStart(ident)
-                    },
-                    "end" => {
-                        if args.len() > 0 {
-                            fatal!("@end takes no args"; args[0]);
-                        }
-
-                        SubroutineEnd
-                    },
-                    "call" => {
-                        if args.len() == 0 {
-                            fatal!("expected (name, args...), found `)`"; stmt);
-                        }
-
-                        // Get subroutine name
-                        let ident = if let MacroArgument::Ident(ref ident) = args[0].value {
-                            ident.clone()
-                        } else {
-                            fatal!("expected subroutine name, found `{}`", args[0]; args[0]);
-                        };
-
-                        // Verify argument count
-                        let routine_argc = *self.routines.get(&ident).unwrap_or_else(|| {
-                            fatal!("unknown subroutine: {}", ident; stmt);
-                        });
-
-                        if args.len() - 1 != routine_argc {
-                            fatal!("wrong argument count: found {} args, expected {}",
-                                   args.len() - 1, routine_argc; args[0]);
-                        }
-
-                        // Get args (cloned)
-                        let args: Vec<_> = args[1..].iter()
-                            .cloned()
-                            .collect();
-
-                        SubroutineCall(ident, args)
-                    }
-                    _ => state.clone()
-                }
-            },
-            _ => state.clone()
-        }
-    }
-}
\ No newline at end of file
+//! A syntax extension for custom subroutines
+//!
+//! # Example:
+//!
+//! Subroutine call:
+//!
+//! ```
+//! @call(name, arg1, arg2)
+//! @call(name, arg1, arg2 -> result1, result2)
+//! ```
+//!
+//! FIXME: Maybe use @name(arg1, arg2) instead?
+//!
+//! Subroutine definition:
+//!
+//! ```
+//! @start(name, argc)
+//!    ...
+//! @end()
+//! ```
+//!
+//! Subroutine definition with named parameters and results:
+//!
+//! ```
+//! @start(name, param1, param2 -> result1, result2)
+//!    ...
+//! @end()
+//! ```
+//!
+//! The arguments are passed in `$arg0`, `$arg1`, ... and the results are
+//! returned in `$return`, `$return1`, ... Named parameters and results are
+//! bound to these slots inside the subroutine body.
+//!
+//! Inlined subroutine call:
+//!
+//! ```
+//! @inline(name, arg1, arg2 -> result1, result2)
+//! ```
+//!
+//! Pastes the subroutine body at the call site. Labels are renamed and
+//! arguments are substituted directly where the body allows it (they are
+//! neither written to nor used where only an address is accepted). Otherwise
+//! they are copied into `$arg0`, ... as for a regular call. Results that
+//! aren't bound at the call site are only reset if the body writes to them.
+//!
+//! Local variables:
+//!
+//! ```
+//! @start(name, param)
+//!     $counter = [_]
+//!     ...
+//! @end()
+//! ```
+//!
+//! Auto-allocated constants defined inside a subroutine body are only visible
+//! in that body. They don't keep their value between calls, so the memory can
+//! be shared with the locals of subroutines that are never active at the same
+//! time.
+//!
+//! # Note:
+//!
+//! Only subroutines that are called from the main program (directly or
+//! through other subroutines) are part of the binary.
+//!
+//! As there is only one `$jump_back` slot, subroutines can't `@call` other
+//! subroutines (including themselves). They can `@inline` them though, as long
+//! as this doesn't end up in a recursion.
+//!
+//! In object files (`tiny asm -c`) all subroutines are kept, as they may be
+//! called from other objects. Calls of subroutines that aren't defined in the
+//! file are checked by the linker.
+
+use std::borrow::ToOwned;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use assembler::parser::ast::{Program, Statement, StatementNode, Argument, ArgumentNode, MacroArgument,
+                             MacroArgumentNode, Ident, Mnemonic};
+use assembler::parser::{Parser, SourceLocation};
+use assembler::util::fatal;
+use assembler::parser::syntax_ext::{Context, operation};
+use machine::{InstructionManager, Argument as ArgumentType};
+
+use self::SubroutineState::*;
+
+
+pub fn expand(source: &mut Program, ctx: &mut Context) {
+    SubroutineExpander {
+        source: source,
+        routines: HashMap::new(),
+        external: vec![],
+        object: ctx.object,
+        im: InstructionManager::new()
+    }.expand(ctx);
+}
+
+
+/// A subroutine call that has been inlined
+pub struct InlineSite {
+    pub name: Ident,
+    pub location: SourceLocation,
+    pub inline_size: usize,     // Bytes of the pasted body
+    pub call_size: usize        // Bytes a regular call would have taken
+}
+
+/// A subroutine body that has been removed because it's never called
+pub struct RemovedRoutine {
+    pub name: Ident,
+    pub location: SourceLocation,
+    pub size: usize,            // Bytes of the removed body
+    pub inlined: bool,          // Whether the subroutine is still used by `@inline`
+    pub constants: Vec<Ident>   // Auto-allocated constants only the body used
+}
+
+/// A subroutine body that's part of the binary
+pub struct EmittedRoutine {
+    pub name: Ident,
+    pub location: SourceLocation,
+    pub size: usize,            // Bytes of the body (including the return jump)
+    pub params: Vec<(Ident, Ident)>,    // The name of each parameter and its slot
+    pub results: Vec<(Ident, Ident)>    // The name of each result and its slot
+}
+
+/// A call of a subroutine that's defined in another object file
+pub struct ExternalCall {
+    pub name: Ident,
+    pub location: SourceLocation,
+    pub argc: usize,
+    pub retc: usize             // 0 if the results are omitted
+}
+
+/// The local variables of a subroutine
+pub struct Frame {
+    pub routine: Ident,
+    pub locals: Vec<Ident>,         // The scoped names of the local variables
+    pub inlined_into: Vec<Ident>    // Subroutines the body is pasted into
+}
+
+
+/// Find the emitted subroutine the statement at `pos` belongs to (as an index
+/// into `emitted`). The code of a subroutine starts at its label and takes
+/// `size` bytes.
+pub fn routine_at(source: &Program, emitted: &[EmittedRoutine], pos: usize) -> Option<usize> {
+    emitted.iter().position(|routine| {
+        let label = Statement::Label(routine.name.clone());
+        let start = match source.iter().position(|stmt| stmt.value == label) {
+            Some(start) if start <= pos => start,
+            _ => return false
+        };
+
+        let offset = source[start .. pos].iter().fold(0, |size, stmt| size + stmt.value.size());
+        offset < routine.size
+    })
+}
+
+
+// --- Subroutine Expansion: Implementation -------------------------------------
+
+// We use a state machine to keep track of where we are and what is allowed.
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum SubroutineState {
+    SubroutineStart(Ident), // Definition of a new subroutine
+    InSubroutine,               // Subroutine body
+    SubroutineEnd,              // End of the body
+    SubroutineCall(Ident, Vec<MacroArgumentNode>, Vec<MacroArgumentNode>),  // Call of a subroutine
+    NotInSubroutine            // Everything else
+}
+
+/// The signature of a subroutine
+#[derive(Debug)]
+struct Subroutine {
+    params: Vec<Ident>,     // Named parameters (empty if only `argc` was given)
+    results: Vec<Ident>,    // Named results
+    argc: usize,
+    retc: usize,
+    body: Vec<StatementNode>,   // The statements between `@start` and `@end`
+    locals: Vec<StatementNode>, // The declarations of the local variables
+    location: SourceLocation
+}
+
+impl Subroutine {
+    /// Get the slots the named parameters, results and local variables are
+    /// bound to
+    fn bindings(&self, name: &Ident) -> HashMap<Ident, Ident> {
+        let mut bindings = HashMap::new();
+
+        for local in self.local_names().into_iter() {
+            bindings.insert(local.clone(), local_slot(name, &local));
+        }
+
+        for (i, param) in self.params.iter().enumerate() {
+            bindings.insert(param.clone(), arg_slot(i));
+        }
+
+        for (i, result) in self.results.iter().enumerate() {
+            bindings.insert(result.clone(), return_slot(i));
+        }
+
+        bindings
+    }
+
+    /// Get all names the n-th argument can be referred to by in the body
+    fn param_names(&self, n: usize) -> Vec<Ident> {
+        let mut names = vec![arg_slot(n)];
+        names.extend(self.params.get(n).cloned().into_iter());
+        names
+    }
+
+    /// Get all names the n-th result can be referred to by in the body
+    fn result_names(&self, n: usize) -> Vec<Ident> {
+        let mut names = vec![return_slot(n)];
+        names.extend(self.results.get(n).cloned().into_iter());
+        names
+    }
+
+    /// Get the names of the local variables
+    fn local_names(&self) -> Vec<Ident> {
+        self.locals.iter()
+            .map(|stmt| match stmt.value {
+                Statement::Const(ref name, _) => name.clone(),
+                _ => unreachable!()
+            })
+            .collect()
+    }
+}
+
+/// A call of a subroutine
+#[derive(Debug)]
+struct Call {
+    callee: Ident,
+    inline: bool,
+    location: SourceLocation
+}
+
+/// The subroutine call graph. Maps the callers (a subroutine or the main
+/// program as `None`) to the calls they make.
+type CallGraph = HashMap<Option<Ident>, Vec<Call>>;
+
+/// The name of the constant a local variable of a subroutine is stored in
+fn local_slot(routine: &Ident, name: &Ident) -> Ident {
+    Ident(format!("{}.{}", routine, name))
+}
+
+/// The name of the constant the n-th argument is passed in
+fn arg_slot(n: usize) -> Ident {
+    Ident(format!("arg{}", n))
+}
+
+/// The name of the constant the n-th result is returned in
+fn return_slot(n: usize) -> Ident {
+    if n == 0 {
+        Ident("return".to_owned())
+    } else {
+        Ident(format!("return{}", n))
+    }
+}
+
+struct SubroutineExpander<'a> {
+    source: &'a mut Program,
+    routines: HashMap<Ident, Subroutine>,
+    external: Vec<ExternalCall>,    // Calls of subroutines from other object files
+    object: bool,
+    im: InstructionManager
+}
+
+impl<'a> SubroutineExpander<'a> {
+
+    fn expand(&mut self, ctx: &mut Context) {
+        // Pass 1: Collect definitions and build preamble
+        self.collect_routines();
+
+        let graph = self.build_call_graph();
+        self.check_call_graph(&graph);
+        if !self.object {
+            self.remove_unused(&graph, ctx);
+        }
+        if self.routines.len() == 0 && self.external.len() == 0 {
+            return
+        }
+
+        self.hoist_locals(&graph, ctx);
+
+        // Build preamble
+        self.build_preamble();
+
+        debug!("Subroutines: {:?}", self.routines);
+
+        // Pass 2: Bind named parameters and results
+        self.bind_parameters();
+
+        // Pass 3: Paste inlined subroutines
+        self.expand_inlines(ctx);
+
+        // Pass 4: Replace function definitions
+        self.process_macros(ctx);
+
+        // Pass 5: Remove the subroutine macros (assertions are checked later)
+        self.source.retain(|stmt| {
+            match stmt.value {
+                Statement::Macro(ref ident, _, _) => {
+                    match ident.as_str() {
+                        "start" | "end" | "call" | "inline" => false,
+                        _ => true
+                    }
+                },
+                _ => true
+            }
+        });
+
+        ctx.external_calls = mem::replace(&mut self.external, vec![]);
+    }
+
+    /// Collect all subroutine definitions and store them in `self.routines`.
+    /// Afterwards verify that all calls match the definitions.
+    fn collect_routines(&mut self) {
+        let mut current = None;
+
+        for stmt in self.source.iter() {
+            let (ident, args, results) = match stmt.value {
+                Statement::Macro(ref ident, ref args, ref results) => (ident.clone(), args, results),
+                _ => {
+                    // Collect the body of the current subroutine
+                    if let Some(ref name) = current {
+                        let routine = self.routines.get_mut(name).unwrap();
+
+                        if is_local_declaration(stmt) {
+                            routine.locals.push(stmt.clone());
+                        } else {
+                            routine.body.push(stmt.clone());
+                        }
+                    }
+
+                    continue
+                }
+            };
+
+            if ident.as_str() == "start" {
+                if args.len() == 0 {
+                    fatal!("expected subroutine name, found `)`"; stmt)
+                }
+
+                let name = if let MacroArgument::Ident(ref name) = args[0].value {
+                    name.clone()
+                } else {
+                    fatal!("expected subroutine name, got {}", args[0]; stmt)
+                };
+
+                let routine = parse_signature(stmt, &args[1..], results);
+
+                // Subroutine definition is valid, store it
+                if self.routines.insert(name.clone(), routine).is_some() {
+                    fatal!("redefinition of subroutine: {}", args[0]; stmt)
+                };
+
+                current = Some(name);
+            } else if ident.as_str() == "end" {
+                current = None;
+            } else if let Some(ref name) = current {
+                self.routines.get_mut(name).unwrap().body.push(stmt.clone());
+            }
+        }
+
+        let mut external = vec![];
+        for stmt in self.source.iter() {
+            if let Statement::Macro(ref ident, ref args, ref results) = stmt.value {
+                if ident.as_str() == "call" || ident.as_str() == "inline" {
+                    external.extend(self.check_call(stmt, args, results).into_iter());
+                }
+            }
+        }
+
+        self.external = external;
+    }
+
+    /// Build the call graph of all subroutines
+    fn build_call_graph(&self) -> CallGraph {
+        let mut graph: CallGraph = HashMap::new();
+        graph.insert(None, vec![]);
+        for name in self.routines.keys() {
+            graph.insert(Some(name.clone()), vec![]);
+        }
+
+        let mut current = None;
+        for stmt in self.source.iter() {
+            if let Statement::Macro(ref ident, ref args, _) = stmt.value {
+                match ident.as_str() {
+                    "start" => current = Some(macro_ident(&args[0]).clone()),
+                    "end" => current = None,
+                    "call" | "inline" => {
+                        if !self.routines.contains_key(macro_ident(&args[0])) {
+                            continue  // Defined in another object file
+                        }
+
+                        graph.get_mut(&current).unwrap().push(Call {
+                            callee: macro_ident(&args[0]).clone(),
+                            inline: ident.as_str() == "inline",
+                            location: stmt.location.clone()
+                        });
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Make sure no subroutine calls another subroutine (directly or through
+    /// inlined subroutines) and there is no recursion
+    fn check_call_graph(&self, graph: &CallGraph) {
+        let mut names: Vec<&Ident> = self.routines.keys().collect();
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        for name in names.into_iter() {
+            check_calls_from(graph, &mut vec![name.clone()]);
+        }
+    }
+
+    /// Remove the bodies of all subroutines that are never called from the
+    /// main program along with the auto-allocated constants only they use
+    fn remove_unused(&mut self, graph: &CallGraph, ctx: &mut Context) {
+        // Walk the call graph starting at the main program
+        let mut reachable = HashSet::new();
+        let mut called = HashSet::new();
+        let mut queue = vec![None];
+
+        while let Some(caller) = queue.pop() {
+            for call in graph.get(&caller).unwrap().iter() {
+                if !call.inline {
+                    called.insert(call.callee.clone());
+                }
+
+                if reachable.insert(call.callee.clone()) {
+                    queue.push(Some(call.callee.clone()));
+                }
+            }
+        }
+
+        // Remove the bodies from the source
+        let mut in_unused = false;
+        self.source.retain(|stmt| {
+            if let Statement::Macro(ref ident, ref args, _) = stmt.value {
+                match ident.as_str() {
+                    "start" => in_unused = !called.contains(macro_ident(&args[0])),
+                    "end" => {
+                        let keep = !in_unused;
+                        in_unused = false;
+                        return keep
+                    },
+                    _ => {}
+                }
+            }
+
+            !in_unused
+        });
+
+        // Find the constants that are no longer referenced. The bodies of
+        // inlined subroutines are pasted into the program later on.
+        let inlined_bodies = self.routines.iter()
+            .filter(|&(name, _)| reachable.contains(name) && !called.contains(name))
+            .flat_map(|(_, routine)| routine.body.iter());
+
+        let referenced: HashSet<Ident> = self.source.iter()
+            .chain(inlined_bodies)
+            .flat_map(|stmt| stmt.value.arguments().into_iter())
+            .flat_map(|arg| arg.value.constants().into_iter())
+            .cloned()
+            .collect();
+
+        let mut removed = vec![];
+        let mut unused_consts = HashSet::new();
+
+        let mut names: Vec<Ident> = self.routines.keys()
+            .filter(|name| !called.contains(*name))
+            .cloned()
+            .collect();
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        for name in names.into_iter() {
+            let routine = self.routines.get(&name).unwrap();
+
+            // Locals, parameters, results and their slots aren't globals
+            // of the body
+            let mut bound = routine.local_names();
+            for i in 0 .. routine.argc {
+                bound.extend(routine.param_names(i).into_iter());
+            }
+            for i in 0 .. routine.retc {
+                bound.extend(routine.result_names(i).into_iter());
+            }
+
+            let mut constants = vec![];
+            for stmt in routine.body.iter() {
+                for arg in stmt.value.arguments().into_iter() {
+                    for name in arg.value.constants().into_iter() {
+                        if bound.contains(name) {
+                            continue
+                        }
+
+                        if !referenced.contains(name) && unused_consts.insert(name.clone()) {
+                            constants.push(name.clone());
+                        }
+                    }
+                }
+            }
+
+            removed.push(RemovedRoutine {
+                size: routine.body.iter().fold(0, |size, stmt| size + stmt.value.size())
+                      + 3 * routine.retc + 2,   // MOV $returnX 0, JMP $jump_back
+                location: routine.location.clone(),
+                inlined: reachable.contains(&name),
+                constants: constants,
+                name: name.clone()
+            });
+        }
+
+        // Remove the auto-allocated constants
+        self.source.retain(|stmt| {
+            match stmt.value {
+                Statement::Const(ref name, ref value) => {
+                    !(unused_consts.contains(name) && is_auto_address(&value.value))
+                },
+                _ => true
+            }
+        });
+
+        // Forget subroutines that aren't even inlined
+        for routine in removed.iter() {
+            if !routine.inlined {
+                self.routines.remove(&routine.name);
+            }
+        }
+
+        ctx.removed.extend(removed.into_iter());
+    }
+
+    /// Move the declarations of local variables to the top of the program
+    /// using their scoped names and record them in `ctx.frames`
+    fn hoist_locals(&mut self, graph: &CallGraph, ctx: &mut Context) {
+        let mut in_routine = false;
+        self.source.retain(|stmt| {
+            if let Statement::Macro(ref ident, _, _) = stmt.value {
+                match ident.as_str() {
+                    "start" => in_routine = true,
+                    "end" => in_routine = false,
+                    _ => {}
+                }
+            }
+
+            !(in_routine && is_local_declaration(stmt))
+        });
+
+        let mut names: Vec<Ident> = self.routines.keys().cloned().collect();
+        names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        let mut declarations = vec![];
+
+        for name in names.into_iter() {
+            let routine = self.routines.get(&name).unwrap();
+
+            let mut locals = vec![];
+            for stmt in routine.locals.iter() {
+                let mut stmt = stmt.clone();
+                let slot = match stmt.value {
+                    Statement::Const(ref local, _) => local_slot(&name, local),
+                    _ => unreachable!()
+                };
+
+                if let Statement::Const(ref mut local, _) = stmt.value {
+                    *local = slot.clone();
+                }
+
+                declarations.push(stmt);
+                locals.push(slot);
+            }
+
+            // The subroutines whose body this subroutine is pasted into
+            let mut inlined_into: Vec<Ident> = graph.iter()
+                .filter(|&(caller, calls)| {
+                    calls.iter().any(|call| call.inline && call.callee == name)
+                        && caller.as_ref().map_or(false, |c| self.routines.contains_key(c))
+                })
+                .map(|(caller, _)| caller.clone().unwrap())
+                .collect();
+            inlined_into.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+            ctx.frames.push(Frame {
+                routine: name.clone(),
+                locals: locals,
+                inlined_into: inlined_into
+            });
+        }
+
+        for (i, stmt) in declarations.into_iter().enumerate() {
+            self.source.insert(i, stmt);
+        }
+    }
+
+    /// Verify the argument and result count of a subroutine call. In object
+    /// files, calls of unknown subroutines are returned for the linker.
+    fn check_call(&self, stmt: &StatementNode, args: &[MacroArgumentNode],
+                  results: &[MacroArgumentNode]) -> Option<ExternalCall> {
+        if args.len() == 0 {
+            fatal!("expected (name, args...), found `)`"; stmt);
+        }
+
+        // Get subroutine name
+        let ident = if let MacroArgument::Ident(ref ident) = args[0].value {
+            ident
+        } else {
+            fatal!("expected subroutine name, found `{}`", args[0]; args[0]);
+        };
+
+        for marg in args[1..].iter().chain(results.iter()) {
+            if let MacroArgument::Ident(ref ident) = marg.value {
+                fatal!("expected argument, got `{}`", ident; marg)
+            }
+        }
+
+        let is_call = match stmt.value {
+            Statement::Macro(ref name, _, _) => name.as_str() == "call",
+            _ => unreachable!()
+        };
+
+        let routine = match self.routines.get(ident) {
+            Some(routine) => routine,
+            None if self.object && is_call => {
+                return Some(ExternalCall {
+                    name: ident.clone(),
+                    location: stmt.location.clone(),
+                    argc: args.len() - 1,
+                    retc: results.len()
+                })
+            },
+            None => fatal!("unknown subroutine: {}", ident; stmt)
+        };
+
+        // Verify argument count
+        if args.len() - 1 != routine.argc {
+            fatal!("wrong argument count: found {} args, expected {}",
+                   args.len() - 1, routine.argc; args[0]);
+        }
+
+        // Verify result count (results may be omitted completely)
+        if results.len() > 0 && results.len() != routine.retc {
+            fatal!("wrong result count: found {} results, expected {}",
+                   results.len(), routine.retc; results[0]);
+        }
+
+        None
+    }
+
+    /// Bind the named parameters and results in all subroutine bodies
+    fn bind_parameters(&mut self) {
+        let mut current = None;
+
+        for i in 0 .. self.source.len() {
+            if let Statement::Macro(ref ident, ref args, _) = self.source[i].value {
+                match ident.as_str() {
+                    "start" => {
+                        if let MacroArgument::Ident(ref name) = args[0].value {
+                            current = Some(name.clone());
+                        }
+                        continue
+                    },
+                    "end" => {
+                        current = None;
+                        continue
+                    },
+                    _ => {}
+                }
+            }
+
+            if let Some(ref routine) = current {
+                self.bind_names(i, routine);
+            }
+        }
+    }
+
+    /// Bind the named parameters and results of a subroutine in the
+    /// statement at `pos`
+    fn bind_names(&mut self, pos: usize, routine: &Ident) {
+        let bindings = self.routines.get(routine).unwrap().bindings(routine);
+
+        for arg in self.source[pos].value.arguments_mut() {
+            for name in arg.value.constants_mut().into_iter() {
+                let slot = match bindings.get(&*name) {
+                    Some(slot) => slot.clone(),
+                    None => continue
+                };
+
+                *name = slot;
+            }
+        }
+    }
+
+    /// Replace all `@inline` calls with the body of the subroutine
+    fn expand_inlines(&mut self, ctx: &mut Context) {
+        let mut next_id = 0;
+
+        let mut i = 0;
+        while i < self.source.len() {
+            let (name, args, results) = match self.source[i].value {
+                Statement::Macro(ref ident, ref args, ref results) if ident.as_str() == "inline" => {
+                    // The call has already been verified by `collect_routines`
+                    let name = if let MacroArgument::Ident(ref name) = args[0].value {
+                        name.clone()
+                    } else {
+                        unreachable!()
+                    };
+
+                    (name, args[1..].to_vec(), results.clone())
+                },
+                _ => {
+                    i += 1;
+                    continue
+                }
+            };
+
+            let stmt = self.source.remove(i);
+            let body = self.inline_body(&name, &args, &results, &stmt.location,
+                                        &mut next_id, &mut vec![], &mut ctx.inlined);
+
+            let len = body.len();
+            for (j, stmt) in body.into_iter().enumerate() {
+                self.source.insert(i + j, stmt);
+            }
+
+            i += len;
+        }
+    }
+
+    /// Build the inlined body of a subroutine (including nested `@inline` calls).
+    /// Every inlined call is recorded in `sites`, nested ones before the call
+    /// they are part of.
+    fn inline_body(&self, name: &Ident, args: &[MacroArgumentNode], results: &[MacroArgumentNode],
+                   location: &SourceLocation, next_id: &mut usize, chain: &mut Vec<Ident>,
+                   sites: &mut Vec<InlineSite>) -> Vec<StatementNode> {
+        if chain.contains(name) {
+            fatal(format!("recursive inlining of {}", name), location);
+        }
+        chain.push(name.clone());
+
+        let routine = self.routines.get(name).unwrap();
+        let mut substitutions: HashMap<Ident, Argument> = HashMap::new();
+        let mut prologue = vec![];
+        let mut epilogue = vec![];
+
+        // Local variables (these may be used in expressions, too)
+        let mut locals = HashMap::new();
+        for local in routine.local_names().into_iter() {
+            let slot = local_slot(name, &local);
+            locals.insert(local, slot);
+        }
+
+        // Pass arguments
+        let args: Vec<Argument> = args.iter().map(|marg| macro_argument(marg).value.clone()).collect();
+
+        for (i, arg) in args.iter().enumerate() {
+            let names = routine.param_names(i);
+
+            let value = if self.can_substitute(routine, &names) {
+                arg.clone()
+            } else {
+                prologue.push(operation("MOV", vec![Argument::Const(arg_slot(i)), arg.clone()],
+                                        location));
+                Argument::Const(arg_slot(i))
+            };
+
+            for name in names.into_iter() {
+                substitutions.insert(name, value.clone());
+            }
+        }
+
+        // Pass results (unless they alias an argument)
+        for i in 0 .. routine.retc {
+            let target = match results.get(i) {
+                Some(result) if !args.contains(&macro_argument(result).value) => {
+                    macro_argument(result).value.clone()
+                },
+                Some(result) => {
+                    epilogue.push(operation("MOV", vec![macro_argument(result).value.clone(),
+                                                        Argument::Const(return_slot(i))],
+                                            location));
+                    Argument::Const(return_slot(i))
+                },
+                None => Argument::Const(return_slot(i))
+            };
+
+            // An unbound result slot is only reset if the body writes to it.
+            // Otherwise a value the caller still needs would be clobbered.
+            if results.get(i).is_some() || self.writes_to(routine, &routine.result_names(i)) {
+                prologue.push(operation("MOV", vec![target.clone(), Argument::Literal(0)], location));
+            }
+
+            for name in routine.result_names(i).into_iter() {
+                substitutions.insert(name, target.clone());
+            }
+        }
+
+        // Rename labels
+        let mut labels = HashMap::new();
+        for stmt in routine.body.iter() {
+            if let Statement::Label(ref label) = stmt.value {
+                labels.insert(label.clone(), Ident(format!("inline{}.{}", *next_id, label)));
+            }
+        }
+        *next_id += 1;
+
+        // Build the body
+        let mut body = prologue;
+
+        for stmt in routine.body.iter() {
+            let mut stmt = stmt.clone();
+
+            let renamed = match stmt.value {
+                Statement::Label(ref label) => labels.get(label).cloned(),
+                _ => None
+            };
+            if let Some(label) = renamed {
+                stmt.value = Statement::Label(label);
+            }
+
+            for arg in stmt.value.arguments_mut() {
+                for constant in arg.value.constants_mut().into_iter() {
+                    let slot = match locals.get(&*constant) {
+                        Some(slot) => slot.clone(),
+                        None => continue
+                    };
+
+                    *constant = slot;
+                }
+
+                arg.value = match arg.value {
+                    Argument::Const(ref name) if substitutions.contains_key(name) => {
+                        substitutions.get(name).unwrap().clone()
+                    },
+                    Argument::Label(ref name) if labels.contains_key(name) => {
+                        Argument::Label(labels.get(name).unwrap().clone())
+                    },
+                    _ => continue
+                };
+            }
+
+            // Expand nested inlined calls
+            let nested = match stmt.value {
+                Statement::Macro(ref ident, ref args, ref results) if ident.as_str() == "inline" => {
+                    let name = if let MacroArgument::Ident(ref name) = args[0].value {
+                        name.clone()
+                    } else {
+                        unreachable!()
+                    };
+
+                    Some(self.inline_body(&name, &args[1..], results, &stmt.location,
+                                          next_id, chain, sites))
+                },
+                _ => None
+            };
+
+            match nested {
+                Some(stmts) => body.extend(stmts.into_iter()),
+                None => body.push(stmt)
+            }
+        }
+
+        body.extend(epilogue.into_iter());
+
+        sites.push(InlineSite {
+            name: name.clone(),
+            location: location.clone(),
+            inline_size: body.iter().fold(0, |size, stmt| size + stmt.value.size()),
+            call_size: 3 * routine.argc     // MOV $argX arg
+                     + 3 + 2                // MOV $jump_back, JMP
+                     + 3 * results.len()    // MOV result $returnX
+        });
+
+        chain.pop();
+
+        body
+    }
+
+    /// Check whether an argument can be pasted into a subroutine body directly.
+    /// That's the case if it is never written to and the operations it's used
+    /// in accept both, literals and addresses.
+    fn can_substitute(&self, routine: &Subroutine, names: &[Ident]) -> bool {
+        let is_param = |arg: &ArgumentNode| {
+            match arg.value {
+                Argument::Const(ref name) => names.contains(name),
+                _ => false
+            }
+        };
+
+        for stmt in routine.body.iter() {
+            match stmt.value {
+                Statement::Operation(Mnemonic(ref instr), ref args) => {
+                    let ops = self.im.lookup_operations(instr);
+
+                    for (pos, arg) in args.iter().enumerate() {
+                        if !is_param(arg) {
+                            continue
+                        }
+
+                        let types: Vec<_> = ops.iter()
+                            .filter_map(|op| op.arg_types.get(pos))
+                            .collect();
+
+                        let written = types.iter().any(|ty| match **ty {
+                            ArgumentType::Address => true,
+                            _ => false
+                        });
+                        let literal = types.iter().any(|ty| match **ty {
+                            ArgumentType::Literal => true,
+                            _ => false
+                        });
+                        let value = types.iter().any(|ty| match **ty {
+                            ArgumentType::Value => true,
+                            _ => false
+                        });
+
+                        if written || !(literal && value) {
+                            return false
+                        }
+                    }
+                },
+                Statement::Macro(_, _, ref results) => {
+                    // Results of nested calls are written to
+                    if results.iter().any(|result| is_param(macro_argument(result))) {
+                        return false
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        true
+    }
+
+    /// Check whether a subroutine body writes to any of the given names
+    fn writes_to(&self, routine: &Subroutine, names: &[Ident]) -> bool {
+        let is_name = |arg: &ArgumentNode| {
+            match arg.value {
+                Argument::Const(ref name) => names.contains(name),
+                _ => false
+            }
+        };
+
+        routine.body.iter().any(|stmt| {
+            match stmt.value {
+                Statement::Operation(Mnemonic(ref instr), ref args) => {
+                    let ops = self.im.lookup_operations(instr);
+
+                    args.iter().enumerate().any(|(pos, arg)| {
+                        is_name(arg) && ops.iter().any(|op| match op.arg_types.get(pos) {
+                            Some(&ArgumentType::Address) => true,
+                            _ => false
+                        })
+                    })
+                },
+                Statement::Macro(_, _, ref results) => {
+                    results.iter().any(|result| is_name(macro_argument(result)))
+                },
+                _ => false
+            }
+        })
+    }
+
+    fn parse_and_insert(&mut self, source: &str, pos: usize) {
+        let ast = Parser::new(source, "<internal>").parse();
+
+        for (i, stmt) in ast.into_iter().enumerate() {
+            self.source.insert(pos + i, stmt)
+        }
+    }
+
+    /// Build the preamble for the subroutine machinery.
+    /// Will only be inserted once at
+    ///
+    /// Will look like this:
+    ///
+    /// ```
+    /// $return = [_]     ; The return value
+    /// $jump_back = [_]  ; The return address
+    /// $arg0 = [_]       ; Arguments any subroutine receives
+    /// $return1 = [_]    ; Additional return values
+    /// ```
+    fn build_preamble(&mut self) {
+        let mut template = r###"
+            $return = [_]
+            $jump_back = [_]
+        "###.to_owned();
+
+        let argc = self.routines.values().map(|r| r.argc)
+            .chain(self.external.iter().map(|call| call.argc))
+            .max().unwrap();
+        for i in 0 .. argc {
+            template.push_str(&format!("${} = [_]\n", arg_slot(i)));
+        }
+
+        let retc = self.routines.values().map(|r| r.retc)
+            .chain(self.external.iter().map(|call| call.retc))
+            .max().unwrap();
+        for i in 1 .. retc {
+            template.push_str(&format!("${} = [_]\n", return_slot(i)));
+        }
+
+        self.parse_and_insert(&template, 0);
+    }
+
+    /// Process subroutine definitions and calls
+    fn process_macros(&mut self, ctx: &mut Context) {
+        let mut state = NotInSubroutine;
+        let mut current = None;  // The subroutine we're in and where it starts
+
+        // We use a indexed iteration here because we'll modify the source as we iterate
+        // over it
+        let mut i = 0;
+        while i < self.source.len() {
+            let prev_state = state.clone();
+
+            state = match self.get_state_for(&self.source[i], &state) {
+                /// State processing & transitions
+
+                SubroutineStart(ident) => {
+                    // Build subroutine preamble
+                    self.source.remove(i);
+
+                    let mut template = format!("{}:\n", ident);
+                    for j in 0 .. self.routines.get(&ident).unwrap().retc {
+                        template.push_str(&format!("MOV ${} 0\n", return_slot(j)));
+                    }
+
+                    self.parse_and_insert(&template, i);
+                    current = Some((ident, i));
+
+                    InSubroutine
+                },
+
+                SubroutineEnd => {
+                    // Remember the size of the subroutine (including the epilogue)
+                    if let Some((name, start)) = current.take() {
+                        let size = self.source[start .. i].iter()
+                            .fold(0, |size, stmt| size + stmt.value.size()) + 2;
+
+                        let routine = self.routines.get(&name).unwrap();
+                        let params = (0 .. routine.argc)
+                            .map(|j| (routine.param_names(j).pop().unwrap(), arg_slot(j)))
+                            .collect();
+                        let results = (0 .. routine.retc)
+                            .map(|j| (routine.result_names(j).pop().unwrap(), return_slot(j)))
+                            .collect();
+
+                        ctx.emitted.push(EmittedRoutine {
+                            location: routine.location.clone(),
+                            name: name,
+                            size: size,
+                            params: params,
+                            results: results
+                        });
+                    }
+
+                    // Build subroutine epilogue
+                    self.source.remove(i);
+
+                    self.parse_and_insert("JMP $jump_back\n", i);
+
+                    NotInSubroutine
+                },
+
+                SubroutineCall(name, args, results) => {
+                    self.source.remove(i);
+
+                    let mut template = String::new();
+
+                    // Build arguments
+                    for j in 0 .. args.len() {
+                        let arg = match args[j].value {
+                            MacroArgument::Argument(ref arg) => arg,
+                            ref other => {
+                                fatal!("expected argument, got `{}`", other; args[j])
+                            }
+                        };
+
+                        template.push_str(&format!("MOV $arg{} {}\n", j, arg));
+                    }
+
+                    // Set jumpback
+                    template.push_str(&format!("MOV $jump_back :ret{}\n", i));
+
+                    // Jump to function
+                    template.push_str(&format!("JMP :{}\n", name));
+
+                    // Add label where to continue
+                    template.push_str(&format!("ret{}:\n", i));
+
+                    // Store results
+                    for (j, result) in results.iter().enumerate() {
+                        template.push_str(&format!("MOV {} ${}\n", result, return_slot(j)));
+                    }
+
+                    self.parse_and_insert(&template, i);
+
+                    prev_state  // Return to previous state
+                },
+
+                _ => state // Stay in current state
+            };
+
+            i += 1;
+        }
+    }
+
+    /// Get the current state based on the statement we're currently processing
+    fn get_state_for(&self, stmt: &StatementNode, state: &SubroutineState) -> SubroutineState {
+        match stmt.value {
+            Statement::Macro(ref ident, ref args, ref results) => {
+                match ident.as_str() {
+                    "start" => {
+                        if *state == InSubroutine { fatal!("can't nest subroutines"; stmt); }
+
+                        // Get subroutine name
+                        let ident = if let MacroArgument::Ident(ref ident) = args[0].value {
+                            ident.clone()
+                        } else {
+                            fatal!("expected subroutine name, found `{}`", args[0].value; args[0]);
+                        };
+
+                        SubroutineStart(ident)
+                    },
+                    "end" => {
+                        if args.len() > 0 {
+                            fatal!("@end takes no args"; args[0]);
+                        }
+
+                        SubroutineEnd
+                    },
+                    "call" => {
+                        // The call has already been verified by `collect_routines`
+                        let ident = if let MacroArgument::Ident(ref ident) = args[0].value {
+                            ident.clone()
+                        } else {
+                            unreachable!()
+                        };
+
+                        // Get args and results (cloned)
+                        let args: Vec<_> = args[1..].iter()
+                            .cloned()
+                            .collect();
+
+                        SubroutineCall(ident, args, results.clone())
+                    }
+                    _ => state.clone()
+                }
+            },
+            _ => state.clone()
+        }
+    }
+}
+
+
+/// Check the calls made by the last subroutine in `chain`
+fn check_calls_from(graph: &CallGraph, chain: &mut Vec<Ident>) {
+    let caller = chain[chain.len() - 1].clone();
+
+    for call in graph.get(&Some(caller)).unwrap().iter() {
+        let path = chain.iter()
+            .chain(Some(&call.callee).into_iter())
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .connect(" → ");
+
+        if chain.contains(&call.callee) {
+            fatal(format!("recursive subroutine call: {}", path), &call.location);
+        }
+
+        if !call.inline {
+            fatal(format!("subroutine call would overwrite the return address of the caller: {}",
+                          path), &call.location);
+        }
+
+        chain.push(call.callee.clone());
+        check_calls_from(graph, chain);
+        chain.pop();
+    }
+}
+
+/// Parse the signature of a subroutine definition, either
+/// `@start(name, argc)` or `@start(name, params... -> results...)`
+fn parse_signature(stmt: &StatementNode, args: &[MacroArgumentNode],
+                   results: &[MacroArgumentNode]) -> Subroutine {
+    // Old style: only the number of arguments is given
+    if args.len() == 1 && results.len() == 0 {
+        if let MacroArgument::Argument(ref arg) = args[0].value {
+            if let Argument::Literal(argc) = arg.value {
+                return Subroutine {
+                    params: vec![],
+                    results: vec![],
+                    argc: argc as usize,
+                    retc: 1,
+                    body: vec![],
+                    locals: vec![],
+                    location: stmt.location.clone()
+                }
+            } else {
+                fatal!("expected argument count, got {}", args[0]; stmt)
+            }
+        }
+    }
+
+    // Named parameters and results
+    let mut names: Vec<Ident> = vec![];
+    for marg in args.iter().chain(results.iter()) {
+        let name = if let MacroArgument::Ident(ref name) = marg.value {
+            name.clone()
+        } else {
+            fatal!("expected parameter name, got {}", marg; marg)
+        };
+
+        if names.contains(&name) {
+            fatal!("duplicate parameter name: {}", name; marg)
+        }
+
+        names.push(name);
+    }
+
+    let results = names.split_off(args.len());
+
+    Subroutine {
+        argc: names.len(),
+        retc: results.len(),
+        params: names,
+        results: results,
+        body: vec![],
+        locals: vec![],
+        location: stmt.location.clone()
+    }
+}
+
+/// Check whether a statement in a subroutine body declares a local variable
+fn is_local_declaration(stmt: &StatementNode) -> bool {
+    match stmt.value {
+        Statement::Const(_, ref value) => is_auto_address(&value.value),
+        _ => false
+    }
+}
+
+/// Check whether an argument is an auto-filled address (`[_]` or `[_; n]`)
+fn is_auto_address(arg: &Argument) -> bool {
+    match *arg {
+        Argument::Address(None) | Argument::Array(_) => true,
+        _ => false
+    }
+}
+
+/// Get the identifier of an already verified macro argument
+fn macro_ident(marg: &MacroArgumentNode) -> &Ident {
+    match marg.value {
+        MacroArgument::Ident(ref ident) => ident,
+        _ => unreachable!()
+    }
+}
+
+/// Get the argument of an already verified macro argument
+fn macro_argument(marg: &MacroArgumentNode) -> &ArgumentNode {
+    match marg.value {
+        MacroArgument::Argument(ref arg) => arg,
+        _ => unreachable!()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::ast::Statement;
+    use assembler::parser::{Parser, Context};
+    use assembler::parser::syntax_ext::expand_source;
+    use machine::WordSize;
+
+    /// Expand the subroutines and return the code (without constants)
+    fn expand_code(source: &str) -> (Vec<String>, Context) {
+        let mut program = Parser::new(source, "test.asm").parse();
+        let mut ctx = Context::new();
+        super::expand(&mut program, &mut ctx);
+
+        let code = program.iter()
+            .filter(|stmt| match stmt.value {
+                Statement::Const(..) => false,
+                _ => true
+            })
+            .map(|stmt| stmt.to_string())
+            .collect();
+
+        (code, ctx)
+    }
+
+    #[test]
+    fn test_inline_arguments() {
+        let (code, ctx) = expand_code("$x = [0]\n\
+                                       @inline(double, 5 -> $x)\n\
+                                       HALT\n\
+                                       @start(double, value -> result)\n\
+                                       MOV $result $value\n\
+                                       ADD $result $value\n\
+                                       @end()\n");
+
+        // The literal is pasted into the body, the result is written to `$x`
+        assert_eq!(code, vec!["MOV $x 0", "MOV $x 5", "ADD $x 5", "HALT"]);
+        assert_eq!(ctx.inlined.len(), 1);
+        assert_eq!(ctx.inlined[0].inline_size, 9);
+    }
+
+    #[test]
+    fn test_inline_labels() {
+        let (code, _) = expand_code("@inline(countdown, 3)\n\
+                                     @inline(countdown, 2)\n\
+                                     HALT\n\
+                                     @start(countdown, n)\n\
+                                     loop:\n\
+                                     SUB $n 1\n\
+                                     JGT :loop $n 0\n\
+                                     @end()\n");
+
+        // `$n` is written to, so it's passed in `$arg0`
+        assert_eq!(code, vec!["MOV $arg0 3",
+                              "inline0.loop:",
+                              "SUB $arg0 1",
+                              "JGT :inline0.loop $arg0 0",
+                              "MOV $arg0 2",
+                              "inline1.loop:",
+                              "SUB $arg0 1",
+                              "JGT :inline1.loop $arg0 0",
+                              "HALT"]);
+    }
+
+    #[test]
+    fn test_nested_inline() {
+        let (code, ctx) = expand_code("$x = [0]\n\
+                                       @inline(outer, $x)\n\
+                                       HALT\n\
+                                       @start(outer, a)\n\
+                                       @inline(inner, $a)\n\
+                                       ADD [2] $a\n\
+                                       @end()\n\
+                                       @start(inner, b)\n\
+                                       ADD [1] $b\n\
+                                       @end()\n");
+
+        assert_eq!(code, vec!["ADD [1] $x", "ADD [2] $x", "HALT"]);
+
+        let sites: Vec<_> = ctx.inlined.iter()
+            .map(|site| (site.name.to_string(), site.location.lineno, site.inline_size))
+            .collect();
+        assert_eq!(sites, vec![("inner".to_string(), 5, 3), ("outer".to_string(), 2, 6)]);
+    }
+
+    #[test]
+    fn test_inlined_globals() {
+        let source = "$counter = [_]\n\
+                      @inline(increment)\n\
+                      HALT\n\
+                      @start(increment)\n\
+                      ADD $counter 1\n\
+                      @end()\n";
+
+        // The body is removed but `$counter` is still used by the inlined copy
+        let (code, ctx) = expand_code(source);
+        assert_eq!(code, vec!["ADD $counter 1", "HALT"]);
+        assert!(ctx.removed[0].inlined);
+        assert_eq!(ctx.removed[0].constants.len(), 0);
+
+        let (_, ctx) = expand_source(source);
+        assert!(ctx.memory_map.iter()
+                   .any(|alloc| alloc.symbol.as_ref().map_or(false, |name| name.as_str() == "counter")));
+    }
+
+    #[test]
+    fn test_removed_constants() {
+        let source = "$scale = [_]\n\
+                      HALT\n\
+                      @start(mul, a, b -> product)\n\
+                      MOV $product $a\n\
+                      MUL $product $scale\n\
+                      ADD $return $arg1\n\
+                      @end()\n";
+
+        // Only the global `$scale` goes away with the body
+        let (_, ctx) = expand_code(source);
+        assert!(!ctx.removed[0].inlined);
+        let constants: Vec<&str> = ctx.removed[0].constants.iter()
+            .map(|name| name.as_str())
+            .collect();
+        assert_eq!(constants, vec!["scale"]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_call_in_subroutine() {
+        // `b` would overwrite the return address of `a`
+        expand_code("@call(a)\n\
+                     HALT\n\
+                     @start(a, 0)\n\
+                     @call(b)\n\
+                     @end()\n\
+                     @start(b, 0)\n\
+                     MOV $return 1\n\
+                     @end()\n");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_recursive_inline() {
+        expand_code("@call(a)\n\
+                     HALT\n\
+                     @start(a, 0)\n\
+                     @inline(b)\n\
+                     @end()\n\
+                     @start(b, 0)\n\
+                     @inline(a)\n\
+                     @end()\n");
+    }
+
+    #[test]
+    fn test_inline_in_subroutine() {
+        let (code, _) = expand_code("@call(a)\n\
+                                     HALT\n\
+                                     @start(a, 0)\n\
+                                     @inline(b)\n\
+                                     @end()\n\
+                                     @start(b, 0)\n\
+                                     MOV $return 1\n\
+                                     @end()\n");
+
+        // The body of `b` is pasted into `a`
+        assert_eq!(code[code.len() - 5 ..].to_vec(),
+                   vec!["a:", "MOV $return 0", "MOV $return 0", "MOV $return 1", "JMP $jump_back"]);
+    }
+
+    #[test]
+    fn test_inline_keeps_return() {
+        // `increment` never writes `$return`, so the result of `add` survives
+        let (code, _) = expand_code("$counter = [_]\n\
+                                     @call(add, 3, 4)\n\
+                                     @inline(increment)\n\
+                                     DPRINT $return\n\
+                                     HALT\n\
+                                     @start(add, 2)\n\
+                                     MOV $return $arg0\n\
+                                     ADD $return $arg1\n\
+                                     @end()\n\
+                                     @start(increment, 0)\n\
+                                     ADD $counter 1\n\
+                                     @end()\n");
+
+        let pos = code.iter().position(|line| *line == "DPRINT $return").unwrap();
+        assert_eq!(code[pos - 1 .. pos + 1].to_vec(), vec!["ADD $counter 1", "DPRINT $return"]);
+        assert!(code[.. pos].iter().all(|line| *line != "MOV $return 0"));
+
+        // The same goes for a value set inside a subroutine
+        let (code, _) = expand_code("$counter = [_]\n\
+                                     @call(a)\n\
+                                     HALT\n\
+                                     @start(a, 0)\n\
+                                     MOV $return 5\n\
+                                     @inline(increment)\n\
+                                     @end()\n\
+                                     @start(increment, 0)\n\
+                                     ADD $counter 1\n\
+                                     @end()\n");
+
+        assert_eq!(code[code.len() - 5 ..].to_vec(),
+                   vec!["a:", "MOV $return 0", "MOV $return 5", "ADD $counter 1", "JMP $jump_back"]);
+    }
+
+    /// Get the address a constant has been assigned to
+    fn address_of(ctx: &Context, name: &str) -> WordSize {
+        ctx.memory_map.iter()
+            .find(|alloc| alloc.symbol.as_ref().map_or(false, |symbol| symbol.as_str() == name))
+            .unwrap()
+            .address
+    }
+
+    #[test]
+    fn test_shared_locals() {
+        let (_, ctx) = expand_source("@call(a)\n\
+                                      @call(b)\n\
+                                      HALT\n\
+                                      @start(a, 0)\n\
+                                      $tmp = [_]\n\
+                                      MOV $tmp 1\n\
+                                      MOV $return $tmp\n\
+                                      @end()\n\
+                                      @start(b, 0)\n\
+                                      $tmp = [_]\n\
+                                      MOV $tmp 2\n\
+                                      MOV $return $tmp\n\
+                                      @end()\n");
+
+        // `a` and `b` are never active at the same time
+        assert_eq!(address_of(&ctx, "a.tmp"), address_of(&ctx, "b.tmp"));
+        assert!(address_of(&ctx, "a.tmp") > address_of(&ctx, "jump_back"));
+    }
+
+    #[test]
+    fn test_inlined_locals() {
+        let (_, ctx) = expand_source("@call(a)\n\
+                                      HALT\n\
+                                      @start(a, 0)\n\
+                                      $tmp = [_]\n\
+                                      MOV $tmp 1\n\
+                                      @inline(b)\n\
+                                      ADD $return $tmp\n\
+                                      @end()\n\
+                                      @start(b, 0)\n\
+                                      $tmp = [_]\n\
+                                      MOV $tmp 2\n\
+                                      MOV $return $tmp\n\
+                                      @end()\n");
+
+        let frame = ctx.frames.iter().find(|frame| frame.routine.as_str() == "b").unwrap();
+        assert_eq!(frame.inlined_into.iter().map(|name| name.to_string()).collect::<Vec<_>>(),
+                   vec!["a".to_string()]);
+
+        // The frame of `b` is placed above the frame of `a`
+        assert_eq!(address_of(&ctx, "b.tmp"), address_of(&ctx, "a.tmp") + 1);
+    }
+}
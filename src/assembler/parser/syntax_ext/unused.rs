@@ -0,0 +1,233 @@
+//! A lint that warns about unused definitions
+//!
+//! # Example:
+//!
+//! ```
+//! $counter = [_]
+//! $unused = 5
+//! MOV $counter 0
+//!
+//! unused_label:
+//! HALT
+//! ```
+//!
+//! Results in warnings for `$counter` (written but never read), `$unused` and
+//! `unused_label`. The same goes for subroutines that are never called.
+//!
+//! # Note:
+//!
+//! Names starting with an underscore (like `$_scratch`) are never reported.
+//! Neither are definitions from imported files and symbols generated by other
+//! syntax extensions.
+
+use std::collections::HashSet;
+use assembler::parser::ast::{Program, Statement, Argument, Expression, ExpressionNode, Ident,
+                             Mnemonic};
+use assembler::parser::SourceLocation;
+use assembler::parser::syntax_ext::Context;
+use assembler::util::warn;
+use machine::Mnemonic as Instruction;
+
+
+pub fn check(source: &Program, ctx: &Context) {
+    for (message, location) in find_unused(source, ctx).into_iter() {
+        warn(message, &location);
+    }
+}
+
+
+/// Get the warnings for all unused definitions
+fn find_unused(source: &Program, ctx: &Context) -> Vec<(String, SourceLocation)> {
+    let mut warnings = vec![];
+    let mut labels = HashSet::new();
+    let mut referenced = HashSet::new();
+    let mut read = HashSet::new();
+
+    // Collect all usages
+    for stmt in source.iter() {
+        // The target of MOV and RANDOM is written without being read
+        let write_only = match stmt.value {
+            Statement::Operation(Mnemonic(instr), _) => {
+                instr == Instruction::MOV || instr == Instruction::RANDOM
+            },
+            _ => false
+        };
+
+        let only_referenced = match stmt.value {
+            Statement::Macro(..) => true,   // Like `@assert($table < 64)`
+            _ => false
+        };
+
+        for (i, arg) in stmt.value.arguments().into_iter().enumerate() {
+            let mut names = HashSet::new();
+            collect_names(&arg.value, &mut labels, &mut names);
+
+            if !only_referenced && !(write_only && i == 0) {
+                read.extend(names.iter().cloned());
+            }
+            referenced.extend(names.into_iter());
+        }
+    }
+
+    // Constants that are used by already expanded extensions (like `#if`)
+    referenced.extend(ctx.referenced.iter().cloned());
+    read.extend(ctx.referenced.iter().cloned());
+
+    // Constants that belonged to removed subroutines are reported along with them
+    let mut removed_constants = HashSet::new();
+    for routine in ctx.removed.iter() {
+        removed_constants.extend(routine.constants.iter().cloned());
+    }
+
+    for stmt in source.iter() {
+        if &**stmt.location.filename == "<internal>" || is_imported(&stmt.location, ctx) {
+            continue  // Generated by the subroutine extension or part of a library
+        }
+
+        match stmt.value {
+            Statement::Label(ref name) => {
+                // Labels with a `.` are generated (like `if0.end`). In object
+                // files, labels may be used by other objects.
+                if name.as_str().contains(".") || is_suppressed(name) || ctx.object {
+                    continue
+                }
+
+                if !labels.contains(name) {
+                    warnings.push((format!("unused label: {}", name), stmt.location.clone()));
+                }
+            },
+            Statement::Const(ref name, _) => {
+                if is_suppressed(name) || ctx.defines.contains_key(name)
+                        || removed_constants.contains(name) {
+                    continue
+                }
+
+                let auto_allocated = ctx.memory_map.iter().any(|alloc| {
+                    !alloc.explicit && alloc.symbol.as_ref() == Some(name)
+                });
+
+                if !referenced.contains(name) {
+                    warnings.push((format!("unused constant: ${}", name), stmt.location.clone()));
+                } else if auto_allocated && !read.contains(name) {
+                    warnings.push((format!("${} is written but never read", name),
+                                   stmt.location.clone()));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    for routine in ctx.removed.iter() {
+        if !routine.inlined && !is_imported(&routine.location, ctx)
+                && !is_suppressed(&routine.name) {
+            warnings.push((format!("unused subroutine: {}", routine.name),
+                           routine.location.clone()));
+        }
+    }
+
+    warnings
+}
+
+
+/// Whether a definition comes from an imported file. Libraries usually contain
+/// more than a program needs, so they aren't checked.
+fn is_imported(location: &SourceLocation, ctx: &Context) -> bool {
+    ctx.imports.iter().any(|file| **location.filename == *file)
+}
+
+/// Whether warnings for a name are suppressed (local variables are named
+/// `routine.name`, so only the last part counts)
+fn is_suppressed(name: &Ident) -> bool {
+    name.as_str().split('.').last().unwrap().starts_with("_")
+}
+
+/// Collect the labels and constants an argument uses
+fn collect_names(arg: &Argument, labels: &mut HashSet<Ident>, consts: &mut HashSet<Ident>) {
+    match *arg {
+        Argument::Label(ref name) => { labels.insert(name.clone()); },
+        Argument::Const(ref name) => { consts.insert(name.clone()); },
+        Argument::Expr(ref expr) => collect_expression_names(expr, labels, consts),
+        _ => {}
+    }
+}
+
+fn collect_expression_names(expr: &ExpressionNode, labels: &mut HashSet<Ident>,
+                            consts: &mut HashSet<Ident>) {
+    match expr.value {
+        Expression::Value(ref arg) => collect_names(arg, labels, consts),
+        Expression::Unary(_, ref operand) => collect_expression_names(operand, labels, consts),
+        Expression::Binary(_, ref lhs, ref rhs) => {
+            collect_expression_names(lhs, labels, consts);
+            collect_expression_names(rhs, labels, consts);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::{Parser, Context};
+    use assembler::parser::syntax_ext::{auto_address, subroutines};
+
+    use super::find_unused;
+
+    fn warnings(source: &str, filename: &str, ctx: &mut Context) -> Vec<String> {
+        let mut program = Parser::new(source, filename).parse();
+        auto_address::expand(&mut program, ctx);
+
+        find_unused(&program, ctx).into_iter().map(|(message, _)| message).collect()
+    }
+
+    const SOURCE: &'static str = "$counter = [_]\n\
+                                  $unused = 5\n\
+                                  $limit = 10\n\
+                                  MOV $counter $limit\n\
+                                  unused_label:\n\
+                                  loop:\n\
+                                  JMP :loop\n";
+
+    #[test]
+    fn test_unused() {
+        assert_eq!(warnings(SOURCE, "test.asm", &mut Context::new()),
+                   vec!["$counter is written but never read",
+                        "unused constant: $unused",
+                        "unused label: unused_label"]);
+    }
+
+    #[test]
+    fn test_assertions() {
+        let mut program = Parser::new("$limit = 10\n\
+                                       @call(f)\n\
+                                       HALT\n\
+                                       @start(f, 0)\n\
+                                       MOV $return 1\n\
+                                       @end()\n\
+                                       @assert($limit < 256)\n", "test.asm").parse();
+        let mut ctx = Context::new();
+        subroutines::expand(&mut program, &mut ctx);
+        auto_address::expand(&mut program, &mut ctx);
+
+        // `$limit` is only used by the assertion
+        assert_eq!(find_unused(&program, &ctx).len(), 0);
+    }
+
+    #[test]
+    fn test_suppressed() {
+        let source = "$_scratch = [_]\n\
+                      $_unused = 5\n\
+                      MOV $_scratch 1\n\
+                      _label:\n\
+                      HALT\n";
+
+        assert_eq!(warnings(source, "test.asm", &mut Context::new()).len(), 0);
+    }
+
+    #[test]
+    fn test_exempt_files() {
+        let mut ctx = Context::new();
+        ctx.imports.push("lib.asm".to_string());
+        assert_eq!(warnings(SOURCE, "lib.asm", &mut ctx).len(), 0);
+
+        assert_eq!(warnings(SOURCE, "<internal>", &mut Context::new()).len(), 0);
+    }
+}
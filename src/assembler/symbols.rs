@@ -0,0 +1,142 @@
+//! Symbol files
+//!
+//! A symbol file lists the names the assembler resolved, one per line as
+//! `kind address name`:
+//!
+//! ```
+//! label       0x00  start
+//! label       0x0c  multiply
+//! subroutine  0x0c  multiply
+//! argument    0x01  multiply.a ($arg0)
+//! argument    0x02  multiply.b ($arg1)
+//! result      0x00  multiply.result ($return)
+//! constant    0x00  return
+//! constant    0x01  arg0
+//! constant    0x02  arg1
+//! constant    0x03  counter (4 bytes)
+//! ```
+//!
+//! Labels and subroutines have program addresses, all other symbols have data
+//! addresses. Constants with a plain value (like `$size = 4`) aren't listed.
+
+use std::collections::HashMap;
+use assembler::parser::ast::{Argument, Ident};
+use assembler::parser::Context;
+use machine::WordSize;
+
+
+/// Build the symbol file for a program
+pub fn generate_symbols(ctx: &Context) -> String {
+    let mut symbols = String::new();
+
+    // Labels ordered by their address
+    let mut labels: Vec<(u32, String)> = ctx.labels.iter()
+        .map(|(name, offset)| (*offset, name.as_str().to_string()))
+        .collect();
+    labels.sort();
+
+    for (offset, name) in labels.into_iter() {
+        symbols.push_str(&format!("{:<12}{:#04x}  {}\n", "label", offset, name));
+    }
+
+    // Subroutines and the slots of their arguments and results
+    for routine in ctx.emitted.iter() {
+        let entry = match ctx.labels.get(&routine.name) {
+            Some(offset) => *offset,
+            None => continue
+        };
+        symbols.push_str(&format!("{:<12}{:#04x}  {}\n", "subroutine", entry, routine.name));
+
+        for &(ref name, ref slot) in routine.params.iter() {
+            if let Some(address) = address_of(slot, &ctx.constants) {
+                symbols.push_str(&format!("{:<12}{:#04x}  {}.{} (${})\n",
+                                          "argument", address, routine.name, name, slot));
+            }
+        }
+
+        for &(ref name, ref slot) in routine.results.iter() {
+            if let Some(address) = address_of(slot, &ctx.constants) {
+                symbols.push_str(&format!("{:<12}{:#04x}  {}.{} (${})\n",
+                                          "result", address, routine.name, name, slot));
+            }
+        }
+    }
+
+    // Address constants ordered by their address
+    let mut constants: Vec<(WordSize, String)> = ctx.constants.iter()
+        .filter_map(|(name, _)| {
+            address_of(name, &ctx.constants).map(|address| (address, name.as_str().to_string()))
+        })
+        .collect();
+    constants.sort();
+
+    for (address, name) in constants.into_iter() {
+        let size = ctx.memory_map.iter()
+            .find(|alloc| alloc.symbol.as_ref().map_or(false, |s| s.as_str() == name))
+            .map_or(1, |alloc| alloc.size);
+
+        if size > 1 {
+            symbols.push_str(&format!("{:<12}{:#04x}  {} ({} bytes)\n",
+                                      "constant", address, name, size));
+        } else {
+            symbols.push_str(&format!("{:<12}{:#04x}  {}\n", "constant", address, name));
+        }
+    }
+
+    symbols
+}
+
+
+/// Get the data address a constant stands for
+fn address_of(name: &Ident, constants: &HashMap<Ident, Argument>) -> Option<WordSize> {
+    match constants.get(name) {
+        Some(&Argument::Address(Some(address))) => Some(address),
+        _ => None
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use assembler::parser::ast::{Argument, Ident};
+    use assembler::parser::{Context, expand_source};
+
+    use super::{generate_symbols, address_of};
+
+    #[test]
+    fn test_symbols() {
+        let mut ctx = Context::new();
+        ctx.labels.insert(Ident("loop".to_string()), 3);
+        ctx.labels.insert(Ident("start".to_string()), 0);
+        ctx.constants.insert(Ident("counter".to_string()), Argument::Address(Some(5)));
+        ctx.constants.insert(Ident("size".to_string()), Argument::Literal(4));
+
+        assert_eq!(generate_symbols(&ctx),
+                   "label       0x00  start\n\
+                    label       0x03  loop\n\
+                    constant    0x05  counter\n");
+    }
+
+    #[test]
+    fn test_subroutine_symbols() {
+        let (_, ctx) = expand_source("$x = [_]\n\
+                                      @call(sum, 3, 4 -> $x)\n\
+                                      HALT\n\
+                                      @start(sum, a, b -> total)\n\
+                                      MOV $total $a\n\
+                                      ADD $total $b\n\
+                                      @end()\n");
+
+        let slot = |name: &str| address_of(&Ident(name.to_string()), &ctx.constants).unwrap();
+        let entry = *ctx.labels.get(&Ident("sum".to_string())).unwrap();
+
+        let symbols = generate_symbols(&ctx);
+        assert!(symbols.contains(&format!("label       {:#04x}  sum\n", entry)));
+        assert!(symbols.contains(&format!("subroutine  {:#04x}  sum\n\
+                                           argument    {:#04x}  sum.a ($arg0)\n\
+                                           argument    {:#04x}  sum.b ($arg1)\n\
+                                           result      {:#04x}  sum.total ($return)\n",
+                                          entry, slot("arg0"), slot("arg1"), slot("return"))));
+        assert!(symbols.contains(&format!("constant    {:#04x}  x\n", slot("x"))));
+    }
+}
@@ -0,0 +1,235 @@
+//! The Tiny binary container
+//!
+//! A container wraps the program with a header so the VM can recognize and
+//! verify it. All numbers are little endian:
+//!
+//! | Offset | Size | Content                                              |
+//! |--------|------|------------------------------------------------------|
+//! | 0      | 4    | Magic number `TINY`                                  |
+//! | 4      | 1    | Format version (1)                                   |
+//! | 5      | 1    | ISA variant (0 = the original Tiny instruction set)  |
+//! | 6      | 1    | Flags (bit 0: symbol section, bit 1: debug section)  |
+//! | 7      | 1    | Entry point                                          |
+//! | 8      | 2    | Code length                                          |
+//! | 10     | 4    | CRC-32 of everything after the header                |
+//! | 14     |      | Code, followed by the sections                       |
+//!
+//! Each section starts with its id (1 = symbols, 2 = debug) and its length
+//! (2 bytes). The symbol section contains a `name address` line for every
+//! label, the debug section an `address file:line` line for every instruction.
+
+use std::borrow::ToOwned;
+
+
+pub const MAGIC: &'static [u8] = b"TINY";
+pub const VERSION: u8 = 1;
+pub const ISA_TINY: u8 = 0;
+
+const FLAG_SYMBOLS: u8 = 1 << 0;
+const FLAG_DEBUG: u8 = 1 << 1;
+
+const SECTION_SYMBOLS: u8 = 1;
+const SECTION_DEBUG: u8 = 2;
+
+const HEADER_SIZE: usize = 14;
+
+
+pub struct Container {
+    pub isa: u8,
+    pub entry: u8,
+    pub code: Vec<u8>,
+    pub symbols: Option<String>,
+    pub debug: Option<String>
+}
+
+impl Container {
+    pub fn new(code: Vec<u8>) -> Container {
+        Container {
+            isa: ISA_TINY,
+            entry: 0,
+            code: code,
+            symbols: None,
+            debug: None
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = self.code.clone();
+        let mut flags = 0;
+
+        if let Some(ref symbols) = self.symbols {
+            write_section(&mut body, SECTION_SYMBOLS, symbols);
+            flags |= FLAG_SYMBOLS;
+        }
+        if let Some(ref debug) = self.debug {
+            write_section(&mut body, SECTION_DEBUG, debug);
+            flags |= FLAG_DEBUG;
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(self.isa);
+        bytes.push(flags);
+        bytes.push(self.entry);
+        bytes.extend(u16_bytes(self.code.len() as u16).into_iter());
+        bytes.extend(u32_bytes(crc32(&body)).into_iter());
+        bytes.extend(body.into_iter());
+
+        bytes
+    }
+
+    /// Read and verify a container
+    pub fn from_bytes(bytes: &[u8]) -> Result<Container, String> {
+        if !is_container(bytes) {
+            return Err("not a Tiny binary (missing the `TINY` header)".to_owned())
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err("truncated header".to_owned())
+        }
+
+        let version = bytes[4];
+        let isa = bytes[5];
+        let flags = bytes[6];
+        let entry = bytes[7];
+        let code_size = read_u16(&bytes[8..10]) as usize;
+        let checksum = read_u32(&bytes[10..14]);
+        let body = &bytes[HEADER_SIZE..];
+
+        if version != VERSION {
+            return Err(format!("unsupported format version: {}", version))
+        }
+        if isa != ISA_TINY {
+            return Err(format!("unsupported ISA variant: {}", isa))
+        }
+        if body.len() < code_size {
+            return Err(format!("truncated code: {} of {} bytes", body.len(), code_size))
+        }
+        if crc32(body) != checksum {
+            return Err("checksum mismatch (the binary is corrupted)".to_owned())
+        }
+        if code_size > 0 && entry as usize >= code_size {
+            return Err(format!("entry point outside of the code: {}", entry))
+        }
+
+        let mut container = Container {
+            isa: isa,
+            entry: entry,
+            code: body[..code_size].to_vec(),
+            symbols: None,
+            debug: None
+        };
+
+        // Read the sections
+        let mut rest = &body[code_size..];
+        while rest.len() > 0 {
+            if rest.len() < 3 {
+                return Err("truncated section header".to_owned())
+            }
+
+            let id = rest[0];
+            let size = read_u16(&rest[1..3]) as usize;
+            if rest.len() < 3 + size {
+                return Err(format!("truncated section {}", id))
+            }
+
+            let content = match String::from_utf8(rest[3 .. 3 + size].to_vec()) {
+                Ok(content) => content,
+                Err(_) => return Err(format!("section {} isn't valid UTF-8", id))
+            };
+
+            match id {
+                SECTION_SYMBOLS => container.symbols = Some(content),
+                SECTION_DEBUG => container.debug = Some(content),
+                _ => {}  // Unknown sections are skipped
+            }
+
+            rest = &rest[3 + size..];
+        }
+
+        if container.symbols.is_some() != (flags & FLAG_SYMBOLS != 0)
+                || container.debug.is_some() != (flags & FLAG_DEBUG != 0) {
+            return Err("the sections don't match the header flags".to_owned())
+        }
+
+        Ok(container)
+    }
+}
+
+
+/// Whether the bytes start with the container header
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The CRC-32 (as used by zlib and PNG) of the bytes
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes.iter() {
+        crc ^= *byte as u32;
+        for _ in 0 .. 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn write_section(body: &mut Vec<u8>, id: u8, content: &str) {
+    body.push(id);
+    body.extend(u16_bytes(content.len() as u16).into_iter());
+    body.extend(content.bytes());
+}
+
+fn u16_bytes(value: u16) -> Vec<u8> {
+    vec![value as u8, (value >> 8) as u8]
+}
+
+fn u32_bytes(value: u32) -> Vec<u8> {
+    vec![value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Container, crc32};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut container = Container::new(vec![0x08, 0x00, 0x01, 0xff]);
+        container.symbols = Some("end 3\n".to_string());
+
+        let bytes = container.to_bytes();
+        assert_eq!(bytes[..8].to_vec(), b"TINY\x01\x00\x01\x00".to_vec());
+
+        let read = Container::from_bytes(&bytes).unwrap();
+        assert_eq!(read.code, vec![0x08, 0x00, 0x01, 0xff]);
+        assert_eq!(read.symbols, Some("end 3\n".to_string()));
+        assert_eq!(read.debug, None);
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut bytes = Container::new(vec![0x08, 0x00, 0x01, 0xff]).to_bytes();
+
+        let last = bytes.len() - 1;
+        bytes[last] = 0x00;
+        assert!(Container::from_bytes(&bytes).is_err());
+
+        bytes.pop();
+        assert!(Container::from_bytes(&bytes).is_err());
+    }
+}
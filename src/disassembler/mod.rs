@@ -0,0 +1,344 @@
+//! The disassembler (`tiny disasm`)
+//!
+//! Turns a binary back into assembly that re-assembles to the same bytes. The
+//! address and the bytes of every instruction are added as a comment:
+//!
+//! ```
+//!     MOV [0] 10              ; 0x00  08 00 0a
+//! label_03:
+//!     SUB [0] 1               ; 0x03  0d 00 01
+//!     JGT :label_03 [0] 0     ; 0x06  1f 03 00 00
+//!     HALT                    ; 0x0a  ff
+//! ```
+//!
+//! Jump targets get a label named after their address unless the symbol file
+//! (or the symbol section of the container) has a name for them. Addresses
+//! are written as `[n]` and literals as numbers, so the assembler picks the
+//! same opcode again.
+//!
+//! Bytes that aren't a valid instruction can't be written as assembly. They
+//! only show up in a comment, so the binary won't round-trip.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use container::Container;
+use machine::{Argument, Instruction, InstructionManager, Mnemonic, WordSize};
+use Args;
+
+
+/// An instruction of the binary (or the bytes that couldn't be decoded)
+struct Decoded<'a> {
+    address: usize,
+    bytes: &'a [WordSize],
+    instruction: Option<&'static Instruction>
+}
+
+impl<'a> Decoded<'a> {
+    /// Whether all arguments of the instruction are present
+    fn is_complete(&self) -> bool {
+        self.instruction.map_or(false, |instr| self.bytes.len() == 1 + instr.argc)
+    }
+}
+
+
+pub fn main(args: Args) {
+    let path = Path::new(&args.arg_input);
+    let source = read_file(&path);
+
+    // Verify the container (unless it's a raw binary)
+    let (code, symbols) = if args.flag_raw {
+        (source, None)
+    } else {
+        match Container::from_bytes(&source) {
+            Ok(container) => (container.code, container.symbols),
+            Err(err) => panic!("Can't disassemble {}: {} (use --raw for binaries without a header)",
+                               path.display(), err)
+        }
+    };
+
+    // Get the label names
+    let labels = if !args.flag_symbols.is_empty() {
+        let path = Path::new(&args.flag_symbols);
+        match String::from_utf8(read_file(&path)) {
+            Ok(text) => parse_symbol_file(&text),
+            Err(_) => panic!("Can't read {}: not a text file", path.display())
+        }
+    } else {
+        symbols.map_or(HashMap::new(), |symbols| parse_symbol_section(&symbols))
+    };
+
+    print!("{}", disassemble(&code, &labels));
+}
+
+
+/// Disassemble the code. `labels` contains the names of known program addresses.
+pub fn disassemble(code: &[WordSize], labels: &HashMap<usize, Vec<String>>) -> String {
+    let im = InstructionManager::new();
+
+    // Split the code into instructions
+    let mut decoded = vec![];
+    let mut address = 0;
+    while address < code.len() {
+        let instruction = im.try_decode_opcode(code[address]);
+        let end = cmp::min(address + instruction.map_or(1, |instr| 1 + instr.argc), code.len());
+
+        decoded.push(Decoded {
+            address: address,
+            bytes: &code[address..end],
+            instruction: instruction
+        });
+        address = end;
+    }
+
+    // Labels can only be placed between instructions and have to be valid
+    // identifiers (the symbol file also lists generated labels like `if0.end`)
+    let is_boundary = |address: usize| {
+        address == code.len() || decoded.iter().any(|d| d.address == address)
+    };
+
+    let mut labels: HashMap<usize, Vec<String>> = labels.iter()
+        .filter(|&(address, _)| is_boundary(*address))
+        .map(|(address, names)| {
+            (*address, names.iter().filter(|name| is_ident(name)).cloned().collect())
+        })
+        .filter(|entry: &(usize, Vec<String>)| entry.1.len() > 0)
+        .collect();
+
+    // Name the jump targets
+    for d in decoded.iter() {
+        if let Some(target) = jump_target(d) {
+            if is_boundary(target) && !labels.contains_key(&target) {
+                labels.insert(target, vec![format!("label_{:02x}", target)]);
+            }
+        }
+    }
+
+    let mut text = String::new();
+
+    for d in decoded.iter() {
+        push_labels(&mut text, &labels, d.address);
+
+        let bytes = d.bytes.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .connect(" ");
+
+        let line = match d.instruction {
+            Some(instr) if d.is_complete() => {
+                format!("{:<24}; {:#04x}  {}", render(instr, &d.bytes[1..], &labels),
+                        d.address, bytes)
+            },
+            Some(instr) => {
+                format!("{:<24}; {:#04x}  {} (truncated {:?} instruction)",
+                        "", d.address, bytes, instr.mnem)
+            },
+            None => format!("{:<24}; {:#04x}  {} (unknown opcode)", "", d.address, bytes)
+        };
+
+        text.push_str(&format!("    {}\n", line));
+    }
+
+    push_labels(&mut text, &labels, code.len());
+
+    text
+}
+
+
+/// Format an instruction as assembly
+fn render(instruction: &Instruction, args: &[WordSize],
+          labels: &HashMap<usize, Vec<String>>) -> String {
+    let mut text = format!("{:?}", instruction.mnem);
+
+    for (i, (ty, value)) in instruction.arg_types.iter().zip(args.iter()).enumerate() {
+        let arg = match *ty {
+            Argument::Address | Argument::Value => format!("[{}]", value),
+            Argument::Literal if i == 0 && is_jump(instruction) => {
+                match labels.get(&(*value as usize)) {
+                    Some(names) => format!(":{}", names[0]),
+                    None => value.to_string()
+                }
+            },
+            Argument::Literal => value.to_string()
+        };
+
+        text.push_str(" ");
+        text.push_str(&arg);
+    }
+
+    text
+}
+
+fn push_labels(text: &mut String, labels: &HashMap<usize, Vec<String>>, address: usize) {
+    if let Some(names) = labels.get(&address) {
+        for name in names.iter() {
+            text.push_str(&format!("{}:\n", name));
+        }
+    }
+}
+
+/// Whether the assembler accepts the name as a label
+fn is_ident(name: &str) -> bool {
+    name.chars().next().map_or(false, |c| c.is_alphabetic() && c.is_lowercase())
+        && name.chars().all(|c| (c.is_alphabetic() && c.is_lowercase())
+                                 || c.is_numeric() || c == '_')
+}
+
+fn is_jump(instruction: &Instruction) -> bool {
+    match instruction.mnem {
+        Mnemonic::JMP | Mnemonic::JZ | Mnemonic::JEQ | Mnemonic::JLS | Mnemonic::JGT => true,
+        _ => false
+    }
+}
+
+/// Get the program address an instruction jumps to (if it's a literal)
+fn jump_target(decoded: &Decoded) -> Option<usize> {
+    match decoded.instruction {
+        Some(instr) if decoded.is_complete() && is_jump(instr) => {
+            match instr.arg_types[0] {
+                Argument::Literal => Some(decoded.bytes[1] as usize),
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+
+/// Read the labels from a symbol file written by `tiny asm --symbols`
+fn parse_symbol_file(text: &str) -> HashMap<usize, Vec<String>> {
+    let mut labels = HashMap::new();
+
+    for line in text.lines() {
+        let words: Vec<&str> = line.split(' ').filter(|w| !w.is_empty()).collect();
+        if words.len() != 3 || words[0] != "label" || !words[1].starts_with("0x") {
+            continue
+        }
+
+        let address = words[1][2..].chars().fold(Some(0), |address, c| {
+            address.and_then(|address| c.to_digit(16).map(|d| address * 16 + d as usize))
+        });
+
+        if let Some(address) = address {
+            add_label(&mut labels, address, words[2]);
+        }
+    }
+
+    labels
+}
+
+/// Read the labels from the symbol section of a container (`name address` lines)
+fn parse_symbol_section(text: &str) -> HashMap<usize, Vec<String>> {
+    let mut labels = HashMap::new();
+
+    for line in text.lines() {
+        let words: Vec<&str> = line.split(' ').collect();
+        if words.len() != 2 {
+            continue
+        }
+
+        if let Ok(address) = words[1].parse() {
+            add_label(&mut labels, address, words[0]);
+        }
+    }
+
+    labels
+}
+
+fn add_label(labels: &mut HashMap<usize, Vec<String>>, address: usize, name: &str) {
+    let mut names = labels.remove(&address).unwrap_or(vec![]);
+    names.push(name.to_string());
+    labels.insert(address, names);
+}
+
+fn read_file(path: &Path) -> Vec<u8> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(err) => { panic!("Can't open {}: {}", path.display(), err) }
+    };
+
+    let mut contents = vec![];
+    match file.read_to_end(&mut contents) {
+        Ok(v)  => v,
+        Err(err) => { panic!("Can't read {}: {}", path.display(), err) }
+    };
+
+    contents
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use assembler::assemble;
+    use machine::InstructionManager;
+
+    use super::{disassemble, parse_symbol_file};
+
+    #[test]
+    fn test_disassemble() {
+        let code = [0x08, 0x00, 0x0a, 0x0d, 0x00, 0x01, 0x1f, 0x03, 0x00, 0x00, 0xff, 0x2a, 0x0f];
+
+        assert_eq!(disassemble(&code, &HashMap::new()),
+                   "    MOV [0] 10              ; 0x00  08 00 0a\n\
+                    label_03:\n    \
+                        SUB [0] 1               ; 0x03  0d 00 01\n    \
+                        JGT :label_03 [0] 0     ; 0x06  1f 03 00 00\n    \
+                        HALT                    ; 0x0a  ff\n                            \
+                    ; 0x0b  2a (unknown opcode)\n                            \
+                    ; 0x0c  0f (truncated JMP instruction)\n");
+    }
+
+    #[test]
+    fn test_labels() {
+        let code = [0x0f, 0x02, 0x0f, 0x01];
+
+        let mut labels = HashMap::new();
+        labels.insert(2, vec!["end".to_string(), "if0.end".to_string()]);
+        labels.insert(4, vec!["unused".to_string()]);
+
+        // Jumps into an instruction can't use a label
+        assert_eq!(disassemble(&code, &labels),
+                   "    JMP :end                ; 0x00  0f 02\n\
+                    end:\n    \
+                        JMP 1                   ; 0x02  0f 01\n\
+                    unused:\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let im = InstructionManager::new();
+
+        for opcode in 0 .. 256 {
+            let instruction = match im.try_decode_opcode(opcode as u8) {
+                Some(instruction) => instruction,
+                None => continue
+            };
+
+            // A jump back to the instruction itself and some other arguments
+            let mut code = vec![opcode as u8];
+            code.extend([0, 7, 255][..instruction.argc].iter().cloned());
+
+            let source = disassemble(&code, &HashMap::new());
+            let binary: Vec<u8> = assemble(&source, "disasm.asm").into_iter()
+                .flat_map(|stmt| stmt.into_iter())
+                .collect();
+
+            assert_eq!(binary, code);
+        }
+    }
+
+    #[test]
+    fn test_symbol_file() {
+        let labels = parse_symbol_file("label       0x00  start\n\
+                                        label       0x0c  multiply\n\
+                                        subroutine  0x0c  multiply\n\
+                                        constant    0x03  counter\n");
+
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[0x0c], vec!["multiply".to_string()]);
+    }
+}
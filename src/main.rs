@@ -1,51 +1,78 @@
-#![feature(plugin)]
-
-// Use of unstable libraries
-#![feature(collections)]
-#![feature(core)]
-#![feature(fs)]
-#![feature(io)]
-#![feature(path)]
-#![feature(old_io)]
-#![feature(os)]
-#![feature(std_misc)]
-
-#![plugin(docopt_macros)]
-
-extern crate ansi_term;
-extern crate docopt;
-extern crate env_logger;
-extern crate rand;
-extern crate "rustc-serialize" as rustc_serialize;
-#[macro_use] extern crate lazy_static;
-#[macro_use] extern crate log;
-
-use docopt::Docopt;
-
-mod assembler;
-mod machine;
-mod vm;
-
-docopt!(Args derive Debug, "
-Usage: tiny asm [-v] <input>
-       tiny asm [-v] --bin <input> <output>
-       tiny vm <input>
-       tiny --help
-
-Options:
-    --help  Show this screen.
-");
-
-
-#[cfg(not(test))]
-fn main() {
-    env_logger::init().unwrap();
-
-    let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
-
-    if args.cmd_asm {
-        assembler::main(args)
-    } else {
-        vm::main(args)
-    }
+#![feature(plugin)]
+
+// Use of unstable libraries
+#![feature(collections)]
+#![feature(core)]
+#![feature(fs)]
+#![feature(io)]
+#![feature(path)]
+#![feature(old_io)]
+#![feature(os)]
+#![feature(std_misc)]
+
+#![plugin(docopt_macros)]
+
+extern crate ansi_term;
+extern crate docopt;
+extern crate env_logger;
+extern crate rand;
+extern crate "rustc-serialize" as rustc_serialize;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+
+use docopt::Docopt;
+
+mod assembler;
+mod container;
+mod disassembler;
+mod machine;
+mod vm;
+
+docopt!(Args derive Debug, "
+Usage: tiny asm [options] [-D <define>]... <input>
+       tiny asm [options] [-D <define>]... --bin <input> <output>
+       tiny asm [options] [-D <define>]... -c <input> <output>
+       tiny asm -E [-D <define>]... <input>
+       tiny link [--format <format>] <output> <object>...
+       tiny disasm [--raw] [--symbols <symbols>] <input>
+       tiny vm [--raw] <input>
+       tiny --help
+
+Options:
+    -v                   Print the source and the expanded source.
+    -D <define>          Define a constant (name=value or just name for 1).
+                         Names are lowercase like in the source.
+    -E                   Only print the source with all extensions expanded.
+    -c                   Write an object file for `tiny link`.
+    -O                   Run the peephole optimizer and remove unreachable
+                         code.
+    --memory-map         Print the data memory layout.
+    --size-report        Print the program size by file and subroutine.
+    --listing <listing>  Write an assembly listing to the given file.
+    --cfg <cfg>          Write the control-flow graph as a Graphviz file.
+    --symbols <symbols>  Write the addresses of all symbols to the given file
+                         (read the label names from it for disasm).
+    --format <format>    The output format: dump, tiny, raw, ihex, srec,
+                         readmemh, logisim, rust, c or python (default: dump,
+                         raw with --bin).
+    --raw                Read a binary without a container header.
+    --help               Show this screen.
+");
+
+
+#[cfg(not(test))]
+fn main() {
+    env_logger::init().unwrap();
+
+    let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
+
+    if args.cmd_asm {
+        assembler::main(args)
+    } else if args.cmd_link {
+        assembler::link(args)
+    } else if args.cmd_disasm {
+        disassembler::main(args)
+    } else {
+        vm::main(args)
+    }
 }
\ No newline at end of file
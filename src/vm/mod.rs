@@ -2,13 +2,11 @@ use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
-use machine::{InstructionManager, Memset, Jump, Halt, Continue};
+use container::Container;
+use machine::{InstructionManager, Memset, Jump, Halt, Continue, MEMORY_SIZE};
 use Args;
 
 
-const MEMORY_SIZE: usize = 256;
-
-
 pub fn main(args: Args) {
     // Read binary file
     let path = Path::new(&args.arg_input);
@@ -23,13 +21,25 @@ pub fn main(args: Args) {
         Err(err) => { panic!("Can't read {}: {}", path.display(), err) }
     };
 
+    // Verify the container (unless it's a raw binary)
+    let (code, entry) = if args.flag_raw {
+        (source, 0)
+    } else {
+        match Container::from_bytes(&source) {
+            Ok(container) => (container.code, container.entry),
+            Err(err) => panic!("Can't run {}: {} (use --raw for binaries without a header)",
+                               path.display(), err)
+        }
+    };
+
     // Run virtual machine
-    run(&source);
+    run(&code, entry as usize);
 }
 
-fn run(source: &[u8]) {
+/// Run the code starting at `entry`. Returns the memory after the program halted.
+pub fn run(source: &[u8], entry: usize) -> Vec<u8> {
     let mut memory = [0u8; MEMORY_SIZE];
-    let mut ip = 0;
+    let mut ip = entry;
     let im = InstructionManager::new();
 
     loop {
@@ -71,4 +81,6 @@ fn run(source: &[u8]) {
         ip += 1;  // Skip opcode
         ip += argc;  // Skip args
     }
+
+    memory.to_vec()
 }
\ No newline at end of file